@@ -0,0 +1,288 @@
+//! Async client for the simple-awg-api `/v1` HTTP API.
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), simple_awg_api_client::Error> {
+//! use simple_awg_api_client::{Client, types::CreateRequest};
+//!
+//! let client = Client::new("http://127.0.0.1:9101")?;
+//! let user = client.create_user(&CreateRequest { name: "alice".into(), group: "office".into(), ..Default::default() }).await?;
+//! client.delete_user(&user.id).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::fmt;
+
+use reqwest::{Method, RequestBuilder, Response, Url};
+use serde::de::DeserializeOwned;
+
+pub use reqwest::StatusCode;
+pub use simple_awg_api_types as types;
+use types::{CreateRequest, Device, DeviceRequest, ErrorBody, GroupRecord, GroupSettings, Job, JobRef, ListQuery, LocationRequest, ObfuscationParams, ObfuscationRotation, Page, RegenerateQuery, RegenerateReport, ReloadReport, Ruleset, ServerInfo, ServerKeyRotation, ServerKeyStatus, StatsQuery, UpdateRequest, UserResource, UserStats, UserUsage};
+
+#[derive(Debug)]
+pub enum Error {
+    /// The base URL can't be used to build request URLs.
+    InvalidUrl(String),
+    /// The request never got a response, or the response body didn't decode.
+    Http(reqwest::Error),
+    /// The server answered with an error status and a JSON error body.
+    Api { status: StatusCode, body: ErrorBody },
+}
+
+impl Error {
+    /// The server's error code (`not_found`, `validation`, `conflict`, ...), if any.
+    pub fn code(&self) -> Option<&str> {
+        match self {
+            Self::Api { body, .. } => Some(&body.code),
+            _ => None,
+        }
+    }
+
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            Self::Api { status, .. } => Some(*status),
+            Self::Http(e) => e.status(),
+            Self::InvalidUrl(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidUrl(url) => write!(f, "Invalid base URL {}", url),
+            Self::Http(e) => write!(f, "HTTP error: {}", e),
+            Self::Api { status, body } => write!(f, "{} {}: {}", status.as_u16(), body.code, body.message),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Http(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        Self::Http(e)
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Clone)]
+pub struct Client {
+    http: reqwest::Client,
+    base: Url,
+}
+
+impl Client {
+    /// Client for the server at `base_url`, e.g. `http://127.0.0.1:9101`.
+    pub fn new(base_url: &str) -> Result<Self> {
+        Self::with_http(reqwest::Client::new(), base_url)
+    }
+
+    /// Same as [`Client::new`], reusing a configured `reqwest::Client`
+    /// (timeouts, TLS, proxies).
+    pub fn with_http(http: reqwest::Client, base_url: &str) -> Result<Self> {
+        let base: Url = base_url.parse().map_err(|_| Error::InvalidUrl(base_url.to_string()))?;
+        if base.cannot_be_a_base() {
+            return Err(Error::InvalidUrl(base_url.to_string()));
+        }
+        Ok(Self { http, base })
+    }
+
+    /// URL of `segments` under the base. Segments are percent-encoded, so
+    /// client ids (base64 public keys) can be passed as is.
+    fn url(&self, segments: &[&str]) -> Url {
+        let mut url = self.base.clone();
+        url.path_segments_mut().expect("checked in with_http").pop_if_empty().extend(segments);
+        url
+    }
+
+    fn request(&self, method: Method, segments: &[&str]) -> RequestBuilder {
+        self.http.request(method, self.url(segments))
+    }
+
+    async fn send(req: RequestBuilder) -> Result<Response> {
+        let res = req.send().await?;
+        let status = res.status();
+        if status.is_success() {
+            return Ok(res);
+        }
+        let text = res.text().await?;
+        let body = serde_json::from_str(&text).unwrap_or_else(|_| ErrorBody {
+            code: "http".to_string(),
+            message: if text.is_empty() { status.to_string() } else { text },
+            fields: vec![],
+        });
+        Err(Error::Api { status, body })
+    }
+
+    async fn json<T: DeserializeOwned>(req: RequestBuilder) -> Result<T> {
+        Ok(Self::send(req).await?.json().await?)
+    }
+
+    pub async fn list_users(&self, query: &ListQuery) -> Result<Page<UserResource>> {
+        Self::json(self.request(Method::GET, &["v1", "users"]).query(query)).await
+    }
+
+    pub async fn create_user(&self, req: &CreateRequest) -> Result<UserResource> {
+        Self::json(self.request(Method::POST, &["v1", "users"]).json(req)).await
+    }
+
+    /// Starts a background job creating `batch`; poll it with [`Client::job`].
+    pub async fn create_users(&self, batch: &[CreateRequest]) -> Result<JobRef> {
+        Self::json(self.request(Method::POST, &["v1", "users", "batch"]).json(batch)).await
+    }
+
+    pub async fn user(&self, id: &str) -> Result<UserResource> {
+        Self::json(self.request(Method::GET, &["v1", "users", id])).await
+    }
+
+    pub async fn update_user(&self, id: &str, req: &UpdateRequest) -> Result<UserResource> {
+        Self::json(self.request(Method::PATCH, &["v1", "users", id]).json(req)).await
+    }
+
+    pub async fn delete_user(&self, id: &str) -> Result<()> {
+        Self::send(self.request(Method::DELETE, &["v1", "users", id])).await?;
+        Ok(())
+    }
+
+    pub async fn devices(&self, id: &str) -> Result<Vec<Device>> {
+        Self::json(self.request(Method::GET, &["v1", "users", id, "devices"])).await
+    }
+
+    /// Gives the user a further device called `name`.
+    pub async fn add_device(&self, id: &str, name: &str) -> Result<Device> {
+        let req = DeviceRequest { name: name.to_string() };
+        Self::json(self.request(Method::POST, &["v1", "users", id, "devices"]).json(&req)).await
+    }
+
+    pub async fn remove_device(&self, id: &str, device: &str) -> Result<()> {
+        Self::send(self.request(Method::DELETE, &["v1", "users", id, "devices", device])).await?;
+        Ok(())
+    }
+
+    /// Traffic of the user summed over its devices and locations.
+    pub async fn user_usage(&self, id: &str) -> Result<UserUsage> {
+        Self::json(self.request(Method::GET, &["v1", "users", id, "stats"])).await
+    }
+
+    /// Gives the user a new keypair and PSK; the returned user has the new id.
+    pub async fn rotate_keys(&self, id: &str) -> Result<UserResource> {
+        Self::json(self.request(Method::POST, &["v1", "users", id, "rotate"])).await
+    }
+
+    /// Takes the user's peers off the interfaces, keeping their addresses.
+    pub async fn suspend_user(&self, id: &str) -> Result<UserResource> {
+        Self::json(self.request(Method::POST, &["v1", "users", id, "suspend"])).await
+    }
+
+    /// Puts a suspended user's peers back.
+    pub async fn resume_user(&self, id: &str) -> Result<UserResource> {
+        Self::json(self.request(Method::POST, &["v1", "users", id, "resume"])).await
+    }
+
+    /// Gives the user a further config on `server`.
+    pub async fn add_location(&self, id: &str, server: &str) -> Result<UserResource> {
+        let req = LocationRequest { server: server.to_string() };
+        Self::json(self.request(Method::POST, &["v1", "users", id, "locations"]).json(&req)).await
+    }
+
+    pub async fn remove_location(&self, id: &str, server: &str) -> Result<()> {
+        Self::send(self.request(Method::DELETE, &["v1", "users", id, "locations", server])).await?;
+        Ok(())
+    }
+
+    pub async fn groups(&self) -> Result<Vec<GroupRecord>> {
+        Self::json(self.request(Method::GET, &["v1", "groups"])).await
+    }
+
+    pub async fn group_users(&self, group: &str, query: &ListQuery) -> Result<Page<UserResource>> {
+        Self::json(self.request(Method::GET, &["v1", "groups", group, "users"]).query(query)).await
+    }
+
+    pub async fn group_settings(&self, group: &str) -> Result<GroupSettings> {
+        Self::json(self.request(Method::GET, &["v1", "groups", group, "settings"])).await
+    }
+
+    /// Replaces the group's settings and rebuilds its configs to match.
+    pub async fn set_group_settings(&self, group: &str, settings: &GroupSettings) -> Result<RegenerateReport> {
+        Self::json(self.request(Method::PUT, &["v1", "groups", group, "settings"]).json(settings)).await
+    }
+
+    /// Gives the group's share page a new guid; the old link stops working.
+    pub async fn rotate_link(&self, group: &str) -> Result<GroupRecord> {
+        Self::json(self.request(Method::POST, &["v1", "groups", group, "rotate-link"])).await
+    }
+
+    pub async fn stats(&self, query: &StatsQuery) -> Result<Vec<UserStats>> {
+        Self::json(self.request(Method::GET, &["v1", "stats"]).query(query)).await
+    }
+
+    /// Rebuilds every stored client config with the current server settings.
+    /// With `dry_run` only reports what would change.
+    pub async fn regenerate_configs(&self, dry_run: bool) -> Result<RegenerateReport> {
+        Self::json(self.request(Method::POST, &["v1", "configs", "regenerate"]).query(&RegenerateQuery { dry_run })).await
+    }
+
+    pub async fn servers(&self) -> Result<Vec<ServerInfo>> {
+        Self::json(self.request(Method::GET, &["v1", "servers"])).await
+    }
+
+    pub async fn obfuscation(&self, server: &str) -> Result<ObfuscationParams> {
+        Self::json(self.request(Method::GET, &["v1", "servers", server, "obfuscation"])).await
+    }
+
+    /// Puts fresh obfuscation parameters into the server's interface and
+    /// regenerates its client configs; old configs stop connecting.
+    pub async fn rotate_obfuscation(&self, server: &str) -> Result<ObfuscationRotation> {
+        Self::json(self.request(Method::POST, &["v1", "servers", server, "obfuscation", "rotate"])).await
+    }
+
+    /// The server's public key and the clients that have not connected since it was rotated.
+    pub async fn server_key(&self, server: &str) -> Result<ServerKeyStatus> {
+        Self::json(self.request(Method::GET, &["v1", "servers", server, "key"])).await
+    }
+
+    /// Gives the server a new keypair and regenerates its client configs.
+    pub async fn rotate_server_key(&self, server: &str) -> Result<ServerKeyRotation> {
+        Self::json(self.request(Method::POST, &["v1", "servers", server, "key", "rotate"])).await
+    }
+
+    /// The firewall rules enforcing group isolation on `server`.
+    pub async fn rules(&self, server: &str) -> Result<Ruleset> {
+        Self::json(self.request(Method::GET, &["v1", "servers", server, "rules"])).await
+    }
+
+    pub async fn job(&self, id: &str) -> Result<Job> {
+        Self::json(self.request(Method::GET, &["v1", "jobs", id])).await
+    }
+
+    /// Removes every user and share page. Only available as a legacy route.
+    pub async fn clear(&self) -> Result<()> {
+        Self::send(self.request(Method::DELETE, &["users"])).await?;
+        Ok(())
+    }
+
+    /// Highest client id in use in `wg0.conf`. Only available as a legacy route.
+    pub async fn last_id(&self) -> Result<u32> {
+        Self::json(self.request(Method::GET, &["id"])).await
+    }
+
+    /// Reloads the server's config file and templates.
+    pub async fn reload(&self) -> Result<ReloadReport> {
+        Self::json(self.request(Method::POST, &["admin", "reload"])).await
+    }
+
+    /// The server's OpenAPI document.
+    pub async fn openapi(&self) -> Result<serde_json::Value> {
+        Self::json(self.request(Method::GET, &["openapi.json"])).await
+    }
+}
@@ -0,0 +1,19 @@
+use axum::{extract::State, Json};
+use utoipa_axum::{router::OpenApiRouter, routes};
+
+use crate::{api::error::{ApiError, ErrorBody}, interactions::shared::{AppState, ReloadReport}};
+
+pub fn router() -> OpenApiRouter<AppState> {
+    OpenApiRouter::new()
+        .routes(routes!(reload))
+}
+
+/// Reloads the config file and templates, same as SIGHUP. Invalid ones are
+/// rejected and the running ones kept.
+#[utoipa::path(post, path = "/reload", tag = "admin",
+    responses((status = 200, body = ReloadReport), (status = 422, body = ErrorBody)))]
+async fn reload(
+    State(state): State<AppState>,
+) -> Result<Json<ReloadReport>, ApiError> {
+    Ok(Json(state.reload().await?))
+}
@@ -0,0 +1,68 @@
+use axum::{http::StatusCode, response::{IntoResponse, Response}, Json};
+use tracing::{error, warn};
+
+use crate::interactions::error::AwgError;
+
+pub use simple_awg_api_types::ErrorBody;
+
+/// Error returned by handlers, rendered as `{"code": ..., "message": ...}`.
+pub struct ApiError {
+    pub status: StatusCode,
+    pub body: ErrorBody,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, code: &'static str, message: impl Into<String>) -> Self {
+        Self { status, body: ErrorBody { code: code.to_string(), message: message.into(), fields: vec![] } }
+    }
+
+    pub fn not_found(what: impl Into<String>) -> Self {
+        AwgError::NotFound(what.into()).into()
+    }
+}
+
+impl From<AwgError> for ApiError {
+    fn from(e: AwgError) -> Self {
+        let (status, code) = match &e {
+            AwgError::ContainerUnreachable(_) => (StatusCode::SERVICE_UNAVAILABLE, "container_unreachable"),
+            AwgError::ConfigParse(_) => (StatusCode::INTERNAL_SERVER_ERROR, "config_parse"),
+            AwgError::SubnetExhausted => (StatusCode::CONFLICT, "subnet_exhausted"),
+            AwgError::NotFound(_) => (StatusCode::NOT_FOUND, "not_found"),
+            AwgError::Validation(_) => (StatusCode::UNPROCESSABLE_ENTITY, "validation"),
+            AwgError::Conflict(_) => (StatusCode::CONFLICT, "conflict"),
+        };
+        let mut r = Self::new(status, code, e.to_string());
+        if let AwgError::Validation(fields) = e {
+            r.body.fields = fields;
+        }
+        r
+    }
+}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(e: anyhow::Error) -> Self {
+        match AwgError::find(&e) {
+            Some(k) => {
+                let mut r = Self::from(k.clone());
+                if r.status.is_server_error() {
+                    error!("{:?}", e);
+                    r.body.message = format!("{:#}", e);
+                }
+                r
+            }
+            None => {
+                error!("{:?}", e);
+                Self::new(StatusCode::INTERNAL_SERVER_ERROR, "internal", format!("{:#}", e))
+            }
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        if self.status.is_client_error() {
+            warn!("{}: {}", self.body.code, self.body.message);
+        }
+        (self.status, Json(self.body)).into_response()
+    }
+}
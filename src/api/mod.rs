@@ -1,72 +1,105 @@
-use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
-use serde::Deserialize;
-use tracing::error;
+use axum::{extract::{Path, State}, http::StatusCode, response::IntoResponse, Json};
+use utoipa_axum::{router::OpenApiRouter, routes};
 
-use crate::interactions::{shared::AppState, wg0::AwgInterfaceConf};
+use crate::{api::error::{ApiError, ErrorBody}, interactions::{error::AwgError, jobs::{Job, JobRef}, shared::{AppState, GroupRecord, User, UserStats}, wg0::AwgInterfaceConf}, util::middleware};
 
+pub use simple_awg_api_types::CreateRequest;
 
+pub mod admin;
+pub mod error;
+pub mod openapi;
+pub mod v1;
 
+/// Routes that predate `/v1`. Served with deprecation headers and marked
+/// deprecated in the spec.
+pub fn legacy_router() -> OpenApiRouter<AppState> {
+    OpenApiRouter::new()
+        .routes(routes!(user_list, clear, create_users))
+        .routes(routes!(users_stats))
+        .routes(routes!(create_user, delete_user))
+        .routes(routes!(groups))
+        .routes(routes!(last_id))
+        .routes(routes!(job))
+        .layer(axum::middleware::from_fn(middleware::deprecation_middleware))
+}
+
+
+#[utoipa::path(get, path = "/users", tag = "legacy", operation_id = "legacy_user_list",
+    responses((status = 200, body = Vec<User>)))]
 pub async fn user_list(
     State(state): State<AppState>,
 ) -> impl IntoResponse {
     Json(state.user_list().await)
-} 
+}
 
-#[derive(Deserialize)]
-pub struct CreateRequest {
-    name: String,
-    group: String
+pub(crate) async fn validate(state: &AppState, batch: &[CreateRequest]) -> Result<(), AwgError> {
+    let errors = state.validate_create(batch).await;
+    if !errors.is_empty() {
+        return Err(AwgError::Validation(errors));
+    }
+    Ok(())
 }
 
+#[utoipa::path(post, path = "/user", tag = "legacy", operation_id = "legacy_create_user",
+    request_body = CreateRequest,
+    responses((status = 200, body = GroupRecord), (status = "4XX", body = ErrorBody), (status = "5XX", body = ErrorBody)))]
 pub async fn create_user(
     State(state): State<AppState>,
-    Json(CreateRequest{name, group}): Json<CreateRequest>,
-) -> impl IntoResponse {
-    match state.add_user(&name, group).await {
-        Ok(r) => {
-            Json(r).into_response()
-        }
-        Err(e) => {
-            error!("{:?}", e);
-            StatusCode::INTERNAL_SERVER_ERROR.into_response()
-        }
+    Json(req): Json<CreateRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    validate(&state, std::slice::from_ref(&req)).await?;
+    let own_key = req.public_key.is_some();
+    let (id, mut record) = state.add_user(req).await?;
+    if own_key {
+        record.config = state.client_config(&id).await;
     }
+    Ok(Json(record))
 }
 
+#[utoipa::path(post, path = "/users", tag = "legacy", operation_id = "legacy_create_users",
+    request_body = Vec<CreateRequest>,
+    responses((status = 202, body = JobRef), (status = 422, body = ErrorBody)))]
 pub async fn create_users(
     State(state): State<AppState>,
     Json(batch): Json<Vec<CreateRequest>>,
-) -> impl IntoResponse {
-    match state.add_users(batch.into_iter().map(|c| (c.name, c.group)).collect()).await {
-        Ok(r) => {
-            Json(r).into_response()
-        }
-        Err(e) => {
-            error!("{:?}", e);
-            StatusCode::INTERNAL_SERVER_ERROR.into_response()
-        }
-    }
+) -> Result<impl IntoResponse, ApiError> {
+    validate(&state, &batch).await?;
+    let job = state.add_users(batch).await;
+    Ok((StatusCode::ACCEPTED, Json(job)))
 }
 
-#[axum::debug_handler]
+#[utoipa::path(get, path = "/jobs/{id}", tag = "legacy", operation_id = "legacy_job",
+    params(("id" = String, Path, description = "Job id")),
+    responses((status = 200, body = Job), (status = 404, body = ErrorBody)))]
+pub async fn job(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    let job = state.jobs.get(&id).await.ok_or_else(|| ApiError::not_found(format!("Job {}", id)))?;
+    Ok(Json(job))
+}
+
+#[utoipa::path(delete, path = "/user", tag = "legacy", operation_id = "legacy_delete_user",
+    request_body(content = String, description = "Client id"),
+    responses((status = 200), (status = 404, body = ErrorBody), (status = "5XX", body = ErrorBody)))]
 pub async fn delete_user(
     State(state): State<AppState>,
     Json(client_id): Json<String>,
-) -> impl IntoResponse {
-    if let Err(e) = state.rm_by_id(&client_id).await {
-        error!("{:?}", e);
-        (StatusCode::INTERNAL_SERVER_ERROR).into_response()
-    } else {
-        (StatusCode::OK).into_response()
-    } 
-} 
+) -> Result<impl IntoResponse, ApiError> {
+    state.rm_by_id(&client_id).await?;
+    Ok(StatusCode::OK)
+}
 
+#[utoipa::path(get, path = "/stats", tag = "legacy", operation_id = "legacy_users_stats",
+    responses((status = 200, body = Vec<UserStats>)))]
 pub async fn users_stats(
     State(state): State<AppState>,
 ) -> impl IntoResponse {
     Json(state.user_stats().await)
 }
 
+#[utoipa::path(get, path = "/groups", tag = "legacy", operation_id = "legacy_groups",
+    responses((status = 200, body = Vec<GroupRecord>)))]
 pub async fn groups(
     State(state): State<AppState>,
 ) -> impl IntoResponse {
@@ -74,16 +107,21 @@ pub async fn groups(
 }
 
 
+#[utoipa::path(delete, path = "/users", tag = "legacy", operation_id = "legacy_clear",
+    responses((status = 200), (status = "5XX", body = ErrorBody)))]
 pub async fn clear(
     State(state): State<AppState>,
-) -> impl IntoResponse {
-    state.clear().await;
-    (StatusCode::OK).into_response()
+) -> Result<impl IntoResponse, ApiError> {
+    state.clear().await?;
+    Ok(StatusCode::OK)
 }
 
+#[utoipa::path(get, path = "/id", tag = "legacy", operation_id = "legacy_last_id",
+    responses((status = 200, body = u32), (status = "5XX", body = ErrorBody)))]
 pub async fn last_id(
-) -> impl IntoResponse {
-    let r = AwgInterfaceConf::from_docker().await.unwrap().unwrap();
-    let r = (StatusCode::OK, Json(r.get_last_id())).into_response();
-    r
-}
\ No newline at end of file
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, ApiError> {
+    let r = AwgInterfaceConf::fetch(state.default_server()).await?
+        .ok_or(AwgError::ConfigParse("wg0.conf".to_string()))?;
+    Ok(Json(r.get_last_id()))
+}
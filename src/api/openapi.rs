@@ -0,0 +1,92 @@
+use axum::Router;
+use utoipa::{openapi::{Deprecated, OpenApi as Spec}, OpenApi};
+use utoipa_axum::router::OpenApiRouter;
+
+use crate::{api::{admin, legacy_router, v1}, interactions::shared::AppState};
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "simple-awg-api", description = "Manages AmneziaWG peers and their share pages."),
+    tags(
+        (name = "users", description = "Peers and their configs"),
+        (name = "groups", description = "Share pages grouping user configs"),
+        (name = "jobs", description = "Background batch operations"),
+        (name = "servers", description = "AmneziaWG servers and their interface settings"),
+        (name = "admin", description = "Server administration"),
+        (name = "legacy", description = "Pre-v1 routes, kept as deprecated aliases"),
+    )
+)]
+pub struct ApiDoc;
+
+/// Builds the API router together with the spec generated from the same route set.
+pub fn split() -> (Router<AppState>, Spec) {
+    let (router, mut spec) = OpenApiRouter::with_openapi(ApiDoc::openapi())
+        .nest("/v1", v1::router())
+        .nest("/admin", admin::router())
+        .merge(legacy_router())
+        .split_for_parts();
+    for (path, item) in spec.paths.paths.iter_mut() {
+        if path.starts_with("/v1/") || path.starts_with("/admin/") {
+            continue;
+        }
+        for op in [&mut item.get, &mut item.post, &mut item.put, &mut item.patch, &mut item.delete].into_iter().flatten() {
+            op.deprecated = Some(Deprecated::True);
+        }
+    }
+    (router, spec)
+}
+
+/// The API plus `/openapi.json` and, with the `swagger-ui` feature, Swagger UI at `/docs`.
+pub fn router() -> Router<AppState> {
+    let (router, spec) = split();
+    #[cfg(feature = "swagger-ui")]
+    let router = router.merge(utoipa_swagger_ui::SwaggerUi::new("/docs").url("/openapi.json", spec.clone()));
+    #[cfg(not(feature = "swagger-ui"))]
+    let router = router.route("/openapi.json", axum::routing::get(move || async move { axum::Json(spec) }));
+    router
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, http::{Method, Request, StatusCode}};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::interactions::backend::Backend;
+
+    /// Every operation in the spec must be routed: a 405, or a 404 that did not
+    /// come from a handler (handlers always answer with a JSON error body), means
+    /// the router and the spec disagree.
+    #[tokio::test]
+    async fn spec_matches_router() {
+        let stored = std::env::temp_dir().join(format!("{}.save", uuid::Uuid::new_v4().simple()));
+        let state = AppState::with_backend(Backend::fake(), &stored.to_string_lossy());
+        let (router, spec) = split();
+        let router = router.with_state(state);
+
+        let mut operation_ids = std::collections::HashSet::new();
+        for (path, item) in spec.paths.paths.iter() {
+            let uri = path.replace(['{', '}'], "");
+            let ops = [
+                (Method::GET, &item.get),
+                (Method::POST, &item.post),
+                (Method::PUT, &item.put),
+                (Method::PATCH, &item.patch),
+                (Method::DELETE, &item.delete),
+            ];
+            for (method, op) in ops {
+                let Some(op) = op else { continue };
+                let id = op.operation_id.clone().unwrap_or_default();
+                assert!(operation_ids.insert(id.clone()), "duplicate operationId {id}");
+
+                let req = Request::builder().method(method.clone()).uri(&uri).body(Body::empty()).unwrap();
+                let res = router.clone().oneshot(req).await.unwrap();
+                let status = res.status();
+                let body = axum::body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+                assert_ne!(status, StatusCode::METHOD_NOT_ALLOWED, "{method} {path} is in the spec but not routed");
+                assert!(status != StatusCode::NOT_FOUND || !body.is_empty(), "{method} {path} is in the spec but not routed");
+            }
+        }
+        assert!(spec.paths.paths.contains_key("/v1/users/{id}"));
+    }
+}
@@ -0,0 +1,405 @@
+use axum::{extract::{Path, Query, State}, http::StatusCode, response::IntoResponse, Json};
+use utoipa_axum::{router::OpenApiRouter, routes};
+
+use crate::{api::{error::{ApiError, ErrorBody}, validate, CreateRequest}, interactions::{error::AwgError, isolation::Ruleset, jobs::{Job, JobRef}, obfuscation::ObfuscationParams, shared::{AppState, Device, GroupRecord, GroupSettings, ObfuscationRotation, RegenerateReport, ServerInfo, ServerKeyRotation, ServerKeyStatus, UserResource, UserStats, UserUsage}, validation::check_label}};
+
+pub use simple_awg_api_types::{DeviceRequest, ListQuery, LocationRequest, Page, RegenerateQuery, StatsQuery, UpdateRequest};
+
+const DEFAULT_LIMIT: usize = 100;
+const MAX_LIMIT: usize = 1000;
+
+pub fn router() -> OpenApiRouter<AppState> {
+    OpenApiRouter::new()
+        .routes(routes!(list_users, create_user))
+        .routes(routes!(create_users))
+        .routes(routes!(get_user, update_user, delete_user))
+        .routes(routes!(list_devices, add_device))
+        .routes(routes!(remove_device))
+        .routes(routes!(user_usage))
+        .routes(routes!(rotate_keys))
+        .routes(routes!(suspend_user))
+        .routes(routes!(resume_user))
+        .routes(routes!(add_location))
+        .routes(routes!(remove_location))
+        .routes(routes!(list_groups))
+        .routes(routes!(group_users))
+        .routes(routes!(get_group_settings, set_group_settings))
+        .routes(routes!(rotate_link))
+        .routes(routes!(stats))
+        .routes(routes!(regenerate_configs))
+        .routes(routes!(list_servers))
+        .routes(routes!(get_obfuscation))
+        .routes(routes!(rotate_obfuscation))
+        .routes(routes!(get_server_key))
+        .routes(routes!(rotate_server_key))
+        .routes(routes!(get_rules))
+        .routes(routes!(get_job))
+}
+
+fn page<T>(all: Vec<T>, offset: usize, limit: Option<usize>) -> Page<T> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT);
+    let total = all.len();
+    Page { items: all.into_iter().skip(offset).take(limit).collect(), total, offset, limit }
+}
+
+#[utoipa::path(get, path = "/users", tag = "users", params(ListQuery),
+    responses((status = 200, body = Page<UserResource>)))]
+async fn list_users(
+    State(state): State<AppState>,
+    Query(q): Query<ListQuery>,
+) -> impl IntoResponse {
+    let users = state.users(q.group.as_deref(), q.name.as_deref(), q.server.as_deref()).await;
+    Json(page(users, q.offset, q.limit))
+}
+
+#[utoipa::path(post, path = "/users", tag = "users", request_body = CreateRequest,
+    responses((status = 201, body = UserResource), (status = 409, body = ErrorBody), (status = 422, body = ErrorBody), (status = "5XX", body = ErrorBody)))]
+async fn create_user(
+    State(state): State<AppState>,
+    Json(req): Json<CreateRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    validate(&state, std::slice::from_ref(&req)).await?;
+    let own_key = req.public_key.is_some();
+    let (id, _) = state.add_user(req).await?;
+    let mut user = state.user(&id).await.ok_or_else(|| ApiError::not_found(format!("User {}", id)))?;
+    if own_key {
+        user.config = state.client_config(&id).await;
+    }
+    Ok((StatusCode::CREATED, Json(user)))
+}
+
+#[utoipa::path(post, path = "/users/batch", tag = "users", request_body = Vec<CreateRequest>,
+    responses((status = 202, body = JobRef), (status = 422, body = ErrorBody)))]
+async fn create_users(
+    state: State<AppState>,
+    batch: Json<Vec<CreateRequest>>,
+) -> Result<impl IntoResponse, ApiError> {
+    super::create_users(state, batch).await
+}
+
+#[utoipa::path(get, path = "/users/{id}", tag = "users",
+    params(("id" = String, Path, description = "Client id (peer public key), percent-encoded")),
+    responses((status = 200, body = UserResource), (status = 404, body = ErrorBody)))]
+async fn get_user(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Json<UserResource>, ApiError> {
+    let user = state.user(&id).await.ok_or_else(|| ApiError::not_found(format!("User {}", id)))?;
+    Ok(Json(user))
+}
+
+#[utoipa::path(patch, path = "/users/{id}", tag = "users", request_body = UpdateRequest,
+    params(("id" = String, Path, description = "Client id (peer public key), percent-encoded")),
+    responses((status = 200, body = UserResource), (status = 404, body = ErrorBody), (status = 409, body = ErrorBody), (status = 422, body = ErrorBody)))]
+async fn update_user(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Json(req): Json<UpdateRequest>,
+) -> Result<Json<UserResource>, ApiError> {
+    let mut errors = vec![];
+    if let Some(name) = &req.name {
+        check_label("name", name, &mut errors);
+    }
+    if let Some(group) = &req.group {
+        check_label("group", group, &mut errors);
+    }
+    if !errors.is_empty() {
+        return Err(AwgError::Validation(errors).into());
+    }
+    state.update_user(&id, req.name, req.group, req.device_limit).await?;
+    get_user(State(state), Path(id)).await
+}
+
+#[utoipa::path(delete, path = "/users/{id}", tag = "users",
+    params(("id" = String, Path, description = "Client id (peer public key), percent-encoded")),
+    responses((status = 204), (status = 404, body = ErrorBody), (status = "5XX", body = ErrorBody)))]
+async fn delete_user(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    state.rm_by_id(&id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(get, path = "/users/{id}/devices", tag = "users",
+    params(("id" = String, Path, description = "Client id (peer public key), percent-encoded")),
+    responses((status = 200, body = Vec<Device>), (status = 404, body = ErrorBody)))]
+async fn list_devices(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Json<Vec<Device>>, ApiError> {
+    let user = state.user(&id).await.ok_or_else(|| ApiError::not_found(format!("User {}", id)))?;
+    Ok(Json(user.devices))
+}
+
+/// Gives the user a further device, with its own keys and address on the
+/// user's server, within the user's device limit.
+#[utoipa::path(post, path = "/users/{id}/devices", tag = "users", request_body = DeviceRequest,
+    params(("id" = String, Path, description = "Client id (peer public key), percent-encoded")),
+    responses((status = 201, body = Device), (status = 404, body = ErrorBody), (status = 409, body = ErrorBody), (status = 422, body = ErrorBody), (status = "5XX", body = ErrorBody)))]
+async fn add_device(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Json(req): Json<DeviceRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    let mut errors = vec![];
+    check_label("name", &req.name, &mut errors);
+    if !errors.is_empty() {
+        return Err(AwgError::Validation(errors).into());
+    }
+    let device_id = state.add_device(&id, &req.name).await?;
+    let device = state.user(&id).await.and_then(|u| u.devices.into_iter().find(|d| d.id == device_id))
+        .ok_or_else(|| ApiError::not_found(format!("Device {}", device_id)))?;
+    Ok((StatusCode::CREATED, Json(device)))
+}
+
+#[utoipa::path(delete, path = "/users/{id}/devices/{device}", tag = "users",
+    params(("id" = String, Path, description = "Client id (peer public key), percent-encoded"), ("device" = String, Path, description = "Device id (peer public key), percent-encoded")),
+    responses((status = 204), (status = 404, body = ErrorBody), (status = 409, body = ErrorBody), (status = "5XX", body = ErrorBody)))]
+async fn remove_device(
+    State(state): State<AppState>,
+    Path((id, device)): Path<(String, String)>,
+) -> Result<impl IntoResponse, ApiError> {
+    state.remove_device(&id, &device).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Traffic of the user summed over its devices and locations.
+#[utoipa::path(get, path = "/users/{id}/stats", tag = "users",
+    params(("id" = String, Path, description = "Client id (peer public key), percent-encoded")),
+    responses((status = 200, body = UserUsage), (status = 404, body = ErrorBody)))]
+async fn user_usage(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Json<UserUsage>, ApiError> {
+    let usage = state.usage(&id).await.ok_or_else(|| ApiError::not_found(format!("User {}", id)))?;
+    Ok(Json(usage))
+}
+
+/// Gives the user a new keypair and PSK at the same address, e.g. after a key
+/// leaked. The client id is the public key, so the returned user has a new id.
+/// A user that brought its own key only gets a new PSK and its config template.
+#[utoipa::path(post, path = "/users/{id}/rotate", tag = "users",
+    params(("id" = String, Path, description = "Client id (peer public key), percent-encoded")),
+    responses((status = 200, body = UserResource), (status = 404, body = ErrorBody), (status = 409, body = ErrorBody), (status = "5XX", body = ErrorBody)))]
+async fn rotate_keys(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Json<UserResource>, ApiError> {
+    let id = state.rotate_keys(&id).await?;
+    let mut user = state.user(&id).await.ok_or_else(|| ApiError::not_found(format!("User {}", id)))?;
+    if user.own_key {
+        user.config = state.client_config(&id).await;
+    }
+    Ok(Json(user))
+}
+
+/// Takes the user's peers off the interfaces without deleting them: they are
+/// commented out of `wg0.conf` and keep their addresses until resumed.
+#[utoipa::path(post, path = "/users/{id}/suspend", tag = "users",
+    params(("id" = String, Path, description = "Client id (peer public key), percent-encoded")),
+    responses((status = 200, body = UserResource), (status = 404, body = ErrorBody), (status = "5XX", body = ErrorBody)))]
+async fn suspend_user(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Json<UserResource>, ApiError> {
+    state.suspend_user(&id).await?;
+    get_user(State(state), Path(id)).await
+}
+
+/// Puts a suspended user's peers back on the interfaces.
+#[utoipa::path(post, path = "/users/{id}/resume", tag = "users",
+    params(("id" = String, Path, description = "Client id (peer public key), percent-encoded")),
+    responses((status = 200, body = UserResource), (status = 404, body = ErrorBody), (status = "5XX", body = ErrorBody)))]
+async fn resume_user(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Json<UserResource>, ApiError> {
+    state.resume_user(&id).await?;
+    get_user(State(state), Path(id)).await
+}
+
+/// Gives the user a further config, with its own keys and address, on another
+/// server. It is shown next to the user's other configs on the group page.
+#[utoipa::path(post, path = "/users/{id}/locations", tag = "users", request_body = LocationRequest,
+    params(("id" = String, Path, description = "Client id (peer public key), percent-encoded")),
+    responses((status = 201, body = UserResource), (status = 404, body = ErrorBody), (status = 409, body = ErrorBody), (status = "5XX", body = ErrorBody)))]
+async fn add_location(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Json(req): Json<LocationRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    state.add_location(&id, &req.server).await?;
+    let user = get_user(State(state), Path(id)).await?;
+    Ok((StatusCode::CREATED, user))
+}
+
+#[utoipa::path(delete, path = "/users/{id}/locations/{server}", tag = "users",
+    params(("id" = String, Path, description = "Client id (peer public key), percent-encoded"), ("server" = String, Path, description = "Server name")),
+    responses((status = 204), (status = 404, body = ErrorBody), (status = 409, body = ErrorBody), (status = "5XX", body = ErrorBody)))]
+async fn remove_location(
+    State(state): State<AppState>,
+    Path((id, server)): Path<(String, String)>,
+) -> Result<impl IntoResponse, ApiError> {
+    state.remove_location(&id, &server).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(get, path = "/groups", tag = "groups",
+    responses((status = 200, body = Vec<GroupRecord>)))]
+async fn list_groups(
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    Json(state.group_records().await)
+}
+
+#[utoipa::path(get, path = "/groups/{name}/users", tag = "groups",
+    params(("name" = String, Path, description = "Group name"), ListQuery),
+    responses((status = 200, body = Page<UserResource>), (status = 404, body = ErrorBody)))]
+async fn group_users(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Query(q): Query<ListQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    if !state.has_group(&name).await {
+        return Err(ApiError::not_found(format!("Group {}", name)));
+    }
+    let users = state.users(Some(&name), q.name.as_deref(), q.server.as_deref()).await;
+    Ok(Json(page(users, q.offset, q.limit)))
+}
+
+#[utoipa::path(get, path = "/groups/{name}/settings", tag = "groups",
+    params(("name" = String, Path, description = "Group name")),
+    responses((status = 200, body = GroupSettings), (status = 404, body = ErrorBody)))]
+async fn get_group_settings(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<Json<GroupSettings>, ApiError> {
+    Ok(Json(state.group_settings(&name).await?))
+}
+
+/// Replaces the group's settings and rebuilds its configs and page to match,
+/// e.g. `compat = "legacy"` for AmneziaWG 1.0 clients. `server` and
+/// `placement` only apply to users created from now on. `isolation` is
+/// enforced on every server right away.
+#[utoipa::path(put, path = "/groups/{name}/settings", tag = "groups", request_body = GroupSettings,
+    params(("name" = String, Path, description = "Group name")),
+    responses((status = 200, body = RegenerateReport), (status = 404, body = ErrorBody), (status = "5XX", body = ErrorBody)))]
+async fn set_group_settings(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Json(settings): Json<GroupSettings>,
+) -> Result<Json<RegenerateReport>, ApiError> {
+    Ok(Json(state.set_group_settings(&name, settings).await?))
+}
+
+#[utoipa::path(post, path = "/groups/{name}/rotate-link", tag = "groups",
+    params(("name" = String, Path, description = "Group name")),
+    responses((status = 200, body = GroupRecord), (status = 404, body = ErrorBody)))]
+async fn rotate_link(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<Json<GroupRecord>, ApiError> {
+    Ok(Json(state.rotate_link(&name).await?))
+}
+
+#[utoipa::path(get, path = "/stats", tag = "users", params(StatsQuery),
+    responses((status = 200, body = Vec<UserStats>)))]
+async fn stats(
+    State(state): State<AppState>,
+    Query(q): Query<StatsQuery>,
+) -> impl IntoResponse {
+    match q.group {
+        Some(group) => Json(state.group_stats(&group).await),
+        None => Json(state.user_stats().await),
+    }
+}
+
+/// Rebuilds every stored client config with the current settings and server
+/// parameters and rewrites the group pages.
+#[utoipa::path(post, path = "/configs/regenerate", tag = "groups", params(RegenerateQuery),
+    responses((status = 200, body = RegenerateReport), (status = "5XX", body = ErrorBody)))]
+async fn regenerate_configs(
+    State(state): State<AppState>,
+    Query(q): Query<RegenerateQuery>,
+) -> Result<Json<RegenerateReport>, ApiError> {
+    Ok(Json(state.regenerate(q.dry_run).await?))
+}
+
+#[utoipa::path(get, path = "/servers", tag = "servers",
+    responses((status = 200, body = Vec<ServerInfo>)))]
+async fn list_servers(
+    State(state): State<AppState>,
+) -> Json<Vec<ServerInfo>> {
+    Json(state.server_infos().await)
+}
+
+#[utoipa::path(get, path = "/servers/{name}/obfuscation", tag = "servers",
+    params(("name" = String, Path, description = "Server name")),
+    responses((status = 200, body = ObfuscationParams), (status = 404, body = ErrorBody), (status = "5XX", body = ErrorBody)))]
+async fn get_obfuscation(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<Json<ObfuscationParams>, ApiError> {
+    Ok(Json(state.obfuscation(&name).await?))
+}
+
+/// Replaces the server interface's obfuscation parameters with a fresh random
+/// set, restarts the interface and regenerates the server's client configs and
+/// the pages. Clients keep working only once they import their new config.
+#[utoipa::path(post, path = "/servers/{name}/obfuscation/rotate", tag = "servers",
+    params(("name" = String, Path, description = "Server name")),
+    responses((status = 200, body = ObfuscationRotation), (status = 404, body = ErrorBody), (status = "5XX", body = ErrorBody)))]
+async fn rotate_obfuscation(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<Json<ObfuscationRotation>, ApiError> {
+    Ok(Json(state.rotate_obfuscation(&name).await?))
+}
+
+/// The server's public key and, since its last rotation, the clients that have
+/// not connected yet, i.e. have not imported their new config.
+#[utoipa::path(get, path = "/servers/{name}/key", tag = "servers",
+    params(("name" = String, Path, description = "Server name")),
+    responses((status = 200, body = ServerKeyStatus), (status = 404, body = ErrorBody), (status = "5XX", body = ErrorBody)))]
+async fn get_server_key(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<Json<ServerKeyStatus>, ApiError> {
+    Ok(Json(state.server_key_status(&name).await?))
+}
+
+/// Gives the server a new keypair, restarts its interface and regenerates the
+/// server's client configs and the pages. Clients keep working only once they
+/// import their new config; `GET /servers/{name}/key` lists those that haven't.
+#[utoipa::path(post, path = "/servers/{name}/key/rotate", tag = "servers",
+    params(("name" = String, Path, description = "Server name")),
+    responses((status = 200, body = ServerKeyRotation), (status = 404, body = ErrorBody), (status = "5XX", body = ErrorBody)))]
+async fn rotate_server_key(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<Json<ServerKeyRotation>, ApiError> {
+    Ok(Json(state.rotate_server_key(&name).await?))
+}
+
+/// The firewall rules enforcing the groups' isolation on the server, as they
+/// are loaded whenever group membership or settings change.
+#[utoipa::path(get, path = "/servers/{name}/rules", tag = "servers",
+    params(("name" = String, Path, description = "Server name")),
+    responses((status = 200, body = Ruleset), (status = 404, body = ErrorBody)))]
+async fn get_rules(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<Json<Ruleset>, ApiError> {
+    Ok(Json(state.ruleset(&name).await?))
+}
+
+#[utoipa::path(get, path = "/jobs/{id}", tag = "jobs",
+    params(("id" = String, Path, description = "Job id")),
+    responses((status = 200, body = Job), (status = 404, body = ErrorBody)))]
+async fn get_job(
+    state: State<AppState>,
+    id: Path<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    super::job(state, id).await
+}
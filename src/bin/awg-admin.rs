@@ -0,0 +1,507 @@
+use std::process::ExitCode;
+
+use clap::{Parser, Subcommand};
+use serde::Serialize;
+use simple_awg_api_client::{types::{AwgCompat, CreateRequest, Device, GroupRecord, GroupSettings, Isolation, Job, ListQuery, ObfuscationParams, PendingClient, ServerInfo, Site, StatsQuery, UpdateRequest, UserResource, UserStats, UserUsage}, Client, Error};
+
+const EXIT_CODES: &str = "Exit codes:
+  0  success
+  1  request failed (server unreachable or internal error)
+  2  usage error
+  3  user, group or job not found
+  4  rejected: invalid input or conflict";
+
+/// Admin tool for simple-awg-api. Talks to a running server over its HTTP API.
+#[derive(Parser)]
+#[command(name = "awg-admin", after_help = EXIT_CODES)]
+struct Cli {
+    /// Base URL of the API server.
+    #[arg(long, env = "AWG_API_URL", default_value = "http://127.0.0.1:8080", global = true)]
+    url: String,
+    /// Print JSON instead of tables.
+    #[arg(long, global = true)]
+    json: bool,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Manage users.
+    #[command(subcommand)]
+    User(UserCommand),
+    /// Manage groups.
+    #[command(subcommand)]
+    Group(GroupCommand),
+    /// Manage share links.
+    #[command(subcommand)]
+    Link(LinkCommand),
+    /// Manage client configs.
+    #[command(subcommand)]
+    Configs(ConfigsCommand),
+    /// List the AmneziaWG servers the API manages.
+    Servers,
+    /// AmneziaWG obfuscation parameters of a server interface.
+    #[command(subcommand)]
+    Obfuscation(ObfuscationCommand),
+    /// Keypair of a server interface.
+    #[command(subcommand)]
+    Key(KeyCommand),
+    /// Firewall rules enforcing group isolation on a server.
+    Rules {
+        #[arg(default_value = "default")]
+        server: String,
+    },
+    /// Traffic and handshake stats.
+    Stats {
+        #[arg(long)]
+        group: Option<String>,
+    },
+    /// Show a batch job.
+    Job { id: String },
+    /// Reload the server's config file and templates.
+    Reload,
+}
+
+#[derive(Subcommand)]
+enum UserCommand {
+    /// Create a user and publish its config on the group's page.
+    Add {
+        name: String,
+        #[arg(long)]
+        group: String,
+        /// Server to put the peer on; defaults to the group's server.
+        #[arg(long)]
+        server: Option<String>,
+        /// Also give the user a config on this server. Repeatable.
+        #[arg(long = "location")]
+        locations: Vec<String>,
+        /// Public key of a keypair generated on the device; prints a config
+        /// template to fill the private key into.
+        #[arg(long)]
+        public_key: Option<String>,
+        /// Fixed address for the peer, e.g. 10.8.1.50.
+        #[arg(long)]
+        address: Option<String>,
+        /// Make the peer a site routing this subnet behind it. Repeatable.
+        #[arg(long = "route")]
+        routes: Vec<String>,
+        /// With --route: what the site's router sends through the tunnel. Repeatable.
+        #[arg(long = "site-allowed-ip", requires = "routes")]
+        site_allowed_ips: Vec<String>,
+    },
+    /// List users.
+    List {
+        #[arg(long)]
+        group: Option<String>,
+        /// Substring of the user name.
+        #[arg(long)]
+        name: Option<String>,
+        #[arg(long)]
+        server: Option<String>,
+    },
+    /// Show one user.
+    Show { id: String },
+    /// Rename a user, move it to another group or change its device limit.
+    Update {
+        id: String,
+        #[arg(long)]
+        name: Option<String>,
+        #[arg(long)]
+        group: Option<String>,
+        /// 0 lifts the limit.
+        #[arg(long)]
+        device_limit: Option<usize>,
+    },
+    /// Delete a user.
+    Rm { id: String },
+    /// List a user's devices.
+    Devices { id: String },
+    /// Give a user a further device.
+    AddDevice { id: String, name: String },
+    /// Delete one of a user's further devices.
+    RmDevice { id: String, device: String },
+    /// Traffic of a user summed over its devices and locations.
+    Usage { id: String },
+    /// Give a user a new keypair and PSK at the same address. Prints the new id.
+    Rotate { id: String },
+    /// Take a user's peers off the interfaces, keeping their addresses.
+    Suspend { id: String },
+    /// Put a suspended user's peers back.
+    Resume { id: String },
+    /// Give a user a further config on another server.
+    AddLocation { id: String, server: String },
+    /// Delete a user's config on a server other than its own.
+    RmLocation { id: String, server: String },
+}
+
+#[derive(Subcommand)]
+enum GroupCommand {
+    /// List groups and their share page guids.
+    List,
+    /// List the users of a group.
+    Users { name: String },
+    /// Show a group's settings, or change them and rebuild its configs.
+    Settings {
+        name: String,
+        /// AmneziaWG clients the configs are written for.
+        #[arg(long, value_parser = ["full", "legacy"])]
+        compat: Option<String>,
+        /// Pin the group's new users to this server; an empty value unpins it.
+        #[arg(long)]
+        server: Option<String>,
+        /// Placement policy for the group's new users when it is not pinned.
+        #[arg(long, value_parser = ["least_peers", "least_traffic", "free_addresses"])]
+        placement: Option<String>,
+        /// Keep the group's users off other peers: `internet` still lets them
+        /// out to the internet, `closed` drops everything else, `off` lifts it.
+        #[arg(long, value_parser = ["internet", "closed", "off"])]
+        isolation: Option<String>,
+        /// Address or subnet the isolated users may still reach; repeatable.
+        /// Isolates the group if it is not already.
+        #[arg(long)]
+        allow: Vec<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum LinkCommand {
+    /// Move the group's share page to a new guid; the old link stops working.
+    Rotate { group: String },
+}
+
+#[derive(Subcommand)]
+enum ConfigsCommand {
+    /// Rebuild every client config with the current server settings and rewrite the pages.
+    Regenerate {
+        /// Only show what would change.
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum ObfuscationCommand {
+    /// Show the server's current parameters.
+    Show {
+        #[arg(default_value = "default")]
+        server: String,
+    },
+    /// Switch to fresh random parameters, restart the interface and regenerate
+    /// the server's client configs. Clients must re-import their config.
+    Rotate {
+        #[arg(default_value = "default")]
+        server: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum KeyCommand {
+    /// Show the server's public key and the clients that have not connected
+    /// since its last rotation.
+    Show {
+        #[arg(default_value = "default")]
+        server: String,
+    },
+    /// Switch to a new keypair, restart the interface and regenerate the
+    /// server's client configs. Clients must re-import their config.
+    Rotate {
+        #[arg(default_value = "default")]
+        server: String,
+    },
+}
+
+/// Rows for table output.
+trait Table {
+    const HEADERS: &'static [&'static str];
+    fn row(&self) -> Vec<String>;
+}
+
+impl Table for UserResource {
+    const HEADERS: &'static [&'static str] = &["ID", "NAME", "GROUP", "SERVERS", "CREATED"];
+    fn row(&self) -> Vec<String> {
+        let servers = self.locations.iter().map(|l| l.server.as_str()).collect::<Vec<_>>().join(",");
+        vec![self.id.clone(), self.name.clone(), self.group.clone().unwrap_or_default(), servers, self.created.clone()]
+    }
+}
+
+impl Table for ServerInfo {
+    const HEADERS: &'static [&'static str] = &["NAME", "LOCATION", "HOST", "INTERFACE", "USERS"];
+    fn row(&self) -> Vec<String> {
+        vec![self.name.clone(), self.location.clone(), self.host.clone(), self.interface.clone(), self.users.to_string()]
+    }
+}
+
+impl Table for Device {
+    const HEADERS: &'static [&'static str] = &["ID", "NAME", "SERVER", "ADDRESS", "CREATED"];
+    fn row(&self) -> Vec<String> {
+        vec![self.id.clone(), self.name.clone(), self.server.clone(), self.address.clone().unwrap_or_default(), self.created.clone()]
+    }
+}
+
+impl Table for UserUsage {
+    const HEADERS: &'static [&'static str] = &["ID", "NAME", "PEERS", "RECV BYTES", "SENT BYTES"];
+    fn row(&self) -> Vec<String> {
+        vec![self.id.clone(), self.name.clone(), self.peers.len().to_string(), self.recv_bytes.to_string(), self.sent_bytes.to_string()]
+    }
+}
+
+impl Table for GroupRecord {
+    const HEADERS: &'static [&'static str] = &["GROUP", "GUID"];
+    fn row(&self) -> Vec<String> {
+        vec![self.group.clone(), self.guid.clone()]
+    }
+}
+
+impl Table for UserStats {
+    const HEADERS: &'static [&'static str] = &["ID", "NAME", "RECV", "SENT", "LAST SEEN"];
+    fn row(&self) -> Vec<String> {
+        vec![self.uid.clone(), self.name.clone(), self.recv.clone(), self.sent.clone(), self.last_seen.clone()]
+    }
+}
+
+impl Table for Job {
+    const HEADERS: &'static [&'static str] = &["ID", "STATUS", "DONE", "FAILED", "TOTAL"];
+    fn row(&self) -> Vec<String> {
+        let status = serde_json::to_value(self.status).ok().and_then(|v| v.as_str().map(str::to_string)).unwrap_or_default();
+        vec![self.id.clone(), status, self.done.to_string(), self.failed.to_string(), self.total.to_string()]
+    }
+}
+
+impl Table for ObfuscationParams {
+    const HEADERS: &'static [&'static str] = &["JC", "JMIN", "JMAX", "S1", "S2", "H1", "H2", "H3", "H4"];
+    fn row(&self) -> Vec<String> {
+        let mut row: Vec<String> = [self.jc, self.jmin, self.jmax, self.s1, self.s2].iter().map(u32::to_string).collect();
+        row.extend([self.h1.clone(), self.h2.clone(), self.h3.clone(), self.h4.clone()]);
+        row
+    }
+}
+
+impl Table for PendingClient {
+    const HEADERS: &'static [&'static str] = &["ID", "NAME", "GROUP", "LAST HANDSHAKE"];
+    fn row(&self) -> Vec<String> {
+        vec![self.id.clone(), self.name.clone(), self.group.clone().unwrap_or_default(), self.latest_handshake.clone().unwrap_or_default()]
+    }
+}
+
+impl Table for GroupSettings {
+    const HEADERS: &'static [&'static str] = &["COMPAT", "SERVER", "PLACEMENT", "ISOLATION"];
+    fn row(&self) -> Vec<String> {
+        let name = |v: serde_json::Result<serde_json::Value>| v.ok().and_then(|v| v.as_str().map(str::to_string)).unwrap_or_default();
+        let isolation = match &self.isolation {
+            None => String::new(),
+            Some(i) => {
+                let mode = if i.internet { "internet" } else { "closed" };
+                if i.allow.is_empty() { mode.to_string() } else { format!("{} +{}", mode, i.allow.join(",")) }
+            }
+        };
+        vec![name(serde_json::to_value(self.compat)), self.server.clone().unwrap_or_default(), name(serde_json::to_value(self.placement)), isolation]
+    }
+}
+
+struct Output {
+    json: bool,
+}
+
+impl Output {
+    fn print<T: Table + Serialize>(&self, items: &[T]) {
+        if self.json {
+            println!("{}", serde_json::to_string_pretty(items).unwrap_or_default());
+            return;
+        }
+        let rows: Vec<Vec<String>> = items.iter().map(Table::row).collect();
+        let mut widths: Vec<usize> = T::HEADERS.iter().map(|h| h.len()).collect();
+        for row in rows.iter() {
+            for (w, cell) in widths.iter_mut().zip(row) {
+                *w = (*w).max(cell.chars().count());
+            }
+        }
+        let line = |cells: Vec<String>| {
+            let padded: Vec<String> = cells.iter().zip(&widths).map(|(c, w)| format!("{:<w$}", c, w = w)).collect();
+            println!("{}", padded.join("  ").trim_end());
+        };
+        line(T::HEADERS.iter().map(|h| h.to_string()).collect());
+        for row in rows {
+            line(row);
+        }
+    }
+
+    fn one<T: Table + Serialize>(&self, item: T) {
+        if self.json {
+            println!("{}", serde_json::to_string_pretty(&item).unwrap_or_default());
+        } else {
+            self.print(&[item]);
+        }
+    }
+}
+
+async fn run(cli: Cli) -> Result<(), Error> {
+    let client = Client::new(&cli.url)?;
+    let out = Output { json: cli.json };
+    match cli.command {
+        Command::User(UserCommand::Add { name, group, server, locations, public_key, address, routes, site_allowed_ips }) => {
+            let site = (!routes.is_empty()).then_some(Site { routes, allowed_ips: site_allowed_ips });
+            let mut user = client.create_user(&CreateRequest { name, group, server, locations, public_key, address, site }).await?;
+            match user.config.take() {
+                Some(config) if !out.json => print!("{}", config),
+                config => out.one(UserResource { config, ..user }),
+            }
+        }
+        Command::User(UserCommand::List { group, name, server }) => {
+            out.print(&all_users(&client, None, ListQuery { group, name, server, ..Default::default() }).await?)
+        }
+        Command::User(UserCommand::Show { id }) => out.one(client.user(&id).await?),
+        Command::User(UserCommand::Update { id, name, group, device_limit }) => {
+            out.one(client.update_user(&id, &UpdateRequest { name, group, device_limit }).await?)
+        }
+        Command::User(UserCommand::Devices { id }) => out.print(&client.devices(&id).await?),
+        Command::User(UserCommand::AddDevice { id, name }) => out.one(client.add_device(&id, &name).await?),
+        Command::User(UserCommand::RmDevice { id, device }) => client.remove_device(&id, &device).await?,
+        Command::User(UserCommand::Usage { id }) => out.one(client.user_usage(&id).await?),
+        Command::User(UserCommand::Rotate { id }) => out.one(client.rotate_keys(&id).await?),
+        Command::User(UserCommand::Suspend { id }) => out.one(client.suspend_user(&id).await?),
+        Command::User(UserCommand::Resume { id }) => out.one(client.resume_user(&id).await?),
+        Command::User(UserCommand::Rm { id }) => client.delete_user(&id).await?,
+        Command::User(UserCommand::AddLocation { id, server }) => out.one(client.add_location(&id, &server).await?),
+        Command::User(UserCommand::RmLocation { id, server }) => client.remove_location(&id, &server).await?,
+        Command::Group(GroupCommand::List) => out.print(&client.groups().await?),
+        Command::Group(GroupCommand::Users { name }) => {
+            out.print(&all_users(&client, Some(&name), ListQuery::default()).await?)
+        }
+        Command::Group(GroupCommand::Settings { name, compat, server, placement, isolation, allow }) => {
+            let mut settings = client.group_settings(&name).await?;
+            if compat.is_some() || server.is_some() || placement.is_some() || isolation.is_some() || !allow.is_empty() {
+                if let Some(compat) = compat {
+                    settings.compat = if compat == "legacy" { AwgCompat::Legacy } else { AwgCompat::Full };
+                }
+                if let Some(server) = server {
+                    settings.server = Some(server).filter(|s| !s.is_empty());
+                }
+                if let Some(placement) = placement {
+                    settings.placement = placement.parse().ok();
+                }
+                match isolation.as_deref() {
+                    Some("off") => settings.isolation = None,
+                    Some(mode) => settings.isolation.get_or_insert_with(Isolation::default).internet = mode == "internet",
+                    None => {}
+                }
+                if !allow.is_empty() {
+                    settings.isolation.get_or_insert_with(Isolation::default).allow = allow;
+                }
+                let report = client.set_group_settings(&name, &settings).await?;
+                eprintln!("{} of {} configs changed", report.changed.len(), report.total);
+            }
+            out.one(settings);
+        }
+        Command::Link(LinkCommand::Rotate { group }) => out.one(client.rotate_link(&group).await?),
+        Command::Configs(ConfigsCommand::Regenerate { dry_run }) => {
+            let report = client.regenerate_configs(dry_run).await?;
+            if out.json {
+                println!("{}", serde_json::to_string_pretty(&report).unwrap_or_default());
+                return Ok(());
+            }
+            for c in report.changed.iter() {
+                println!("{} ({}, {})", c.name, c.group, c.id);
+                print!("{}", c.diff);
+            }
+            for s in report.skipped.iter() {
+                eprintln!("skipped {} ({}, {}): {}", s.name, s.group, s.id, s.reason);
+            }
+            let verb = if dry_run { "would change" } else { "changed" };
+            println!("{} of {} configs {}", report.changed.len(), report.total, verb);
+        }
+        Command::Servers => out.print(&client.servers().await?),
+        Command::Obfuscation(ObfuscationCommand::Show { server }) => out.one(client.obfuscation(&server).await?),
+        Command::Obfuscation(ObfuscationCommand::Rotate { server }) => {
+            let rotation = client.rotate_obfuscation(&server).await?;
+            if out.json {
+                println!("{}", serde_json::to_string_pretty(&rotation).unwrap_or_default());
+                return Ok(());
+            }
+            out.one(rotation.params);
+            println!("{} of {} configs changed", rotation.regenerated.changed.len(), rotation.regenerated.total);
+        }
+        Command::Key(KeyCommand::Show { server }) => {
+            let status = client.server_key(&server).await?;
+            if out.json {
+                println!("{}", serde_json::to_string_pretty(&status).unwrap_or_default());
+                return Ok(());
+            }
+            println!("Public key: {}", status.public_key);
+            if let Some(rotated_at) = &status.rotated_at {
+                println!("Rotated at {}, {} of {} clients not connected since", rotated_at, status.pending.len(), status.total);
+                out.print(&status.pending);
+            }
+        }
+        Command::Key(KeyCommand::Rotate { server }) => {
+            let rotation = client.rotate_server_key(&server).await?;
+            if out.json {
+                println!("{}", serde_json::to_string_pretty(&rotation).unwrap_or_default());
+                return Ok(());
+            }
+            println!("Public key: {}", rotation.public_key);
+            println!("{} of {} configs changed", rotation.regenerated.changed.len(), rotation.regenerated.total);
+        }
+        Command::Rules { server } => {
+            let ruleset = client.rules(&server).await?;
+            if out.json {
+                println!("{}", serde_json::to_string_pretty(&ruleset).unwrap_or_default());
+                return Ok(());
+            }
+            print!("{}", ruleset.script);
+        }
+        Command::Stats { group } => out.print(&client.stats(&StatsQuery { group }).await?),
+        Command::Job { id } => out.one(client.job(&id).await?),
+        Command::Reload => {
+            let report = client.reload().await?;
+            if out.json {
+                println!("{}", serde_json::to_string_pretty(&report).unwrap_or_default());
+            } else if !report.restart_required.is_empty() {
+                println!("Reloaded; restart to apply: {}", report.restart_required.join(", "));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Follows `offset` until every user of the listing (or of `group`'s listing) is fetched.
+async fn all_users(client: &Client, group: Option<&str>, mut query: ListQuery) -> Result<Vec<UserResource>, Error> {
+    let mut items = vec![];
+    loop {
+        query.offset = items.len();
+        let page = match group {
+            Some(group) => client.group_users(group, &query).await?,
+            None => client.list_users(&query).await?,
+        };
+        let done = page.items.is_empty() || items.len() + page.items.len() >= page.total;
+        items.extend(page.items);
+        if done {
+            return Ok(items);
+        }
+    }
+}
+
+fn exit_code(e: &Error) -> u8 {
+    match e.code() {
+        Some("not_found") => 3,
+        Some("validation" | "conflict" | "subnet_exhausted") => 4,
+        _ => 1,
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    match run(cli).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            if let Error::Api { body, .. } = &e {
+                for f in body.fields.iter() {
+                    eprintln!("  {}: {}", f.field, f.message);
+                }
+            }
+            ExitCode::from(exit_code(&e))
+        }
+    }
+}
@@ -0,0 +1,361 @@
+use std::{collections::BTreeMap, net::{Ipv4Addr, SocketAddr}, path::{Path, PathBuf}, sync::{Arc, RwLock}};
+
+use anyhow::{Context, Result};
+use once_cell::sync::{Lazy, OnceCell};
+use serde::{Deserialize, Serialize};
+use simple_awg_api_types::{FieldError, PlacementPolicy};
+
+/// Server configuration.
+///
+/// Layered from lowest to highest priority: built-in defaults, the TOML file
+/// passed with `--config`, then environment variables named after the keys in
+/// upper case (`HOST`, `STORED_FILE`, ...). A `.env` file in the working
+/// directory is loaded into the environment first, without replacing variables
+/// that are already set.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Address the API listens on.
+    pub addr: String,
+    /// Where users, groups and pages are persisted between restarts.
+    pub stored_file: String,
+
+    // The `default` server, used when `servers` is empty. See `ServerConfig`.
+    pub container: String,
+    pub awg_dir: String,
+    pub interface: String,
+    pub host: String,
+    pub mask: String,
+    pub location: String,
+    pub reserved: Vec<String>,
+
+    pub dns: String,
+    pub keepalive: String,
+
+    /// Holds `config.hbs` and `index.hbs`.
+    pub templates_dir: String,
+    /// Share pages are written to `<served_dir>/<guid>/index.html`.
+    pub served_dir: String,
+
+    pub name_min_len: usize,
+    pub name_max_len: usize,
+    /// Characters allowed in names on top of letters and digits.
+    pub name_extra_chars: String,
+    /// Reject a user name already taken in the same group.
+    pub unique_names: bool,
+    /// Devices a user may have, its own peer included, unless set per user; 0 means no limit.
+    pub device_limit: usize,
+
+    /// Rotate the obfuscation parameters this often; 0 turns it off.
+    pub obfuscation_rotate_hours: u64,
+
+    /// How a server is picked for new users that name none, unless their
+    /// group is pinned to one or has its own policy.
+    pub placement: PlacementPolicy,
+
+    /// AmneziaWG servers by name. Neither `servers` nor `reserved` is read from the environment.
+    pub servers: BTreeMap<String, ServerConfig>,
+}
+
+/// Name of the server made of the top-level keys when no `servers` are configured.
+pub const DEFAULT_SERVER: &str = "default";
+
+/// One AmneziaWG container and interface.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// Docker container running AmneziaWG.
+    pub container: String,
+    /// Directory inside the container holding the interface config and `clientsTable`.
+    pub awg_dir: String,
+    /// Interface name; its config is `<awg_dir>/<interface>.conf`.
+    pub interface: String,
+    /// Server host put into client configs as the endpoint.
+    pub host: String,
+    /// First three octets of the client subnet, with the trailing dot (`10.8.1.`).
+    pub mask: String,
+    /// Shown on share pages next to the configs of users with several
+    /// locations, e.g. `Amsterdam`. Defaults to the server name.
+    pub location: String,
+    /// Addresses never handed out automatically, as `10.8.1.10` or
+    /// `10.8.1.20-10.8.1.29`. Users can still be given one explicitly.
+    pub reserved: Vec<String>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            container: "amnezia-awg".to_string(),
+            awg_dir: "/opt/amnezia/awg".to_string(),
+            interface: "wg0".to_string(),
+            host: String::new(),
+            mask: String::new(),
+            location: String::new(),
+            reserved: vec![],
+        }
+    }
+}
+
+impl ServerConfig {
+    pub fn wg_conf(&self) -> String {
+        format!("{}/{}.conf", self.awg_dir, self.interface)
+    }
+
+    pub fn clients_table(&self) -> String {
+        format!("{}/clientsTable", self.awg_dir)
+    }
+
+    pub fn server_public_key(&self) -> String {
+        format!("{}/wireguard_server_public_key.key", self.awg_dir)
+    }
+
+    pub fn server_private_key(&self) -> String {
+        format!("{}/wireguard_server_private_key.key", self.awg_dir)
+    }
+
+    /// Script loading the groups' isolation rules.
+    pub fn isolation_script(&self) -> String {
+        format!("{}/isolation.sh", self.awg_dir)
+    }
+
+    /// Last octet of `address` if it is a peer address in the server's subnet.
+    pub fn host_id(&self, address: &str) -> Option<u32> {
+        let address: Ipv4Addr = address.trim().parse().ok()?;
+        let id = address.to_string().strip_prefix(&self.mask)?.parse().ok()?;
+        // .1 is the server's own address.
+        (2..=254).contains(&id).then_some(id)
+    }
+
+    /// Host ids of the `reserved` addresses; entries that don't parse are left out.
+    pub fn reserved_ids(&self) -> Vec<u32> {
+        self.reserved.iter().filter_map(|r| self.reserved_range(r)).flatten().collect()
+    }
+
+    fn reserved_range(&self, entry: &str) -> Option<std::ops::RangeInclusive<u32>> {
+        let (lo, hi) = entry.split_once('-').unwrap_or((entry, entry));
+        let (lo, hi) = (self.host_id(lo)?, self.host_id(hi)?);
+        (lo <= hi).then_some(lo..=hi)
+    }
+
+    /// Problems with the server, reported as `<prefix><key>`.
+    fn validate(&self, prefix: &str, errors: &mut Vec<FieldError>) {
+        let field = |key: &str| format!("{}{}", prefix, key);
+        if self.container.is_empty() {
+            errors.push(FieldError::new(field("container"), "must be set"));
+        }
+        if !self.awg_dir.starts_with('/') || !self.awg_dir.chars().all(|c| c.is_ascii_alphanumeric() || "/_.-".contains(c)) {
+            errors.push(FieldError::new(field("awg_dir"), format!("{:?} must be an absolute path of [A-Za-z0-9/_.-]", self.awg_dir)));
+        }
+        if self.interface.is_empty() || self.interface.len() > 15
+            || !self.interface.chars().all(|c| c.is_ascii_alphanumeric() || "_=+.-".contains(c)) {
+            errors.push(FieldError::new(field("interface"), format!("{:?} is not a valid interface name", self.interface)));
+        }
+        if self.host.trim().is_empty() {
+            errors.push(FieldError::new(field("host"), "must be set"));
+        }
+        let octets: Vec<&str> = self.mask.split('.').collect();
+        if octets.len() != 4 || !octets[3].is_empty() || octets[..3].iter().any(|o| o.parse::<u8>().is_err()) {
+            errors.push(FieldError::new(field("mask"), format!("{:?} must be three octets followed by a dot, e.g. \"10.8.1.\"", self.mask)));
+        }
+        for entry in self.reserved.iter().filter(|r| self.reserved_range(r).is_none()) {
+            errors.push(FieldError::new(field("reserved"), format!("{:?} is not an address or low-high range of peer addresses in {}0/24", entry, self.mask)));
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            addr: "0.0.0.0:8080".to_string(),
+            stored_file: "./data/stored.save".to_string(),
+            container: "amnezia-awg".to_string(),
+            awg_dir: "/opt/amnezia/awg".to_string(),
+            interface: "wg0".to_string(),
+            host: String::new(),
+            mask: String::new(),
+            location: String::new(),
+            reserved: vec![],
+            dns: String::new(),
+            keepalive: "25".to_string(),
+            templates_dir: "data/templates".to_string(),
+            served_dir: "data/served".to_string(),
+            name_min_len: 1,
+            name_max_len: 32,
+            name_extra_chars: " -_.@".to_string(),
+            unique_names: true,
+            device_limit: 5,
+            obfuscation_rotate_hours: 0,
+            placement: PlacementPolicy::LeastPeers,
+            servers: BTreeMap::new(),
+        }
+    }
+}
+
+/// Replaces each listed field with the env var of the same name in upper case, if set.
+macro_rules! env_overrides {
+    ($config:ident: $($field:ident),* $(,)?) => {
+        $(
+            let key = stringify!($field).to_ascii_uppercase();
+            if let Ok(v) = std::env::var(&key) {
+                $config.$field = v.parse().map_err(|_| anyhow::anyhow!("Invalid env var {}: {:?}", key, v))?;
+            }
+        )*
+    };
+}
+
+impl Config {
+    /// Defaults, then `file` if given, then the environment.
+    pub fn load(file: Option<&Path>) -> Result<Self> {
+        dotenvy::dotenv().ok(); // only for develop
+        let mut config = match file {
+            Some(path) => {
+                let data = std::fs::read_to_string(path).with_context(|| format!("Failed to read config {}", path.display()))?;
+                toml::from_str(&data).with_context(|| format!("Failed to parse config {}", path.display()))?
+            }
+            None => Config::default(),
+        };
+        env_overrides!(config:
+            addr, stored_file, container, awg_dir, interface, host, mask, location,
+            dns, keepalive, templates_dir, served_dir,
+            name_min_len, name_max_len, name_extra_chars, unique_names, device_limit,
+            obfuscation_rotate_hours, placement,
+        );
+        Ok(config)
+    }
+
+    /// Every problem with the config.
+    pub fn validate(&self) -> Vec<FieldError> {
+        let mut errors = vec![];
+        if self.addr.parse::<SocketAddr>().is_err() {
+            errors.push(FieldError::new("addr", format!("{:?} is not a socket address", self.addr)));
+        }
+        if self.servers.is_empty() {
+            self.servers()[DEFAULT_SERVER].validate("", &mut errors);
+        }
+        for (name, server) in self.servers.iter() {
+            if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || "_-".contains(c)) {
+                errors.push(FieldError::new(format!("servers.{}", name), "name must be made of [A-Za-z0-9_-]"));
+            }
+            server.validate(&format!("servers.{}.", name), &mut errors);
+        }
+        if self.dns.trim().is_empty() {
+            errors.push(FieldError::new("dns", "must be set"));
+        }
+        if self.keepalive.parse::<u16>().is_err() {
+            errors.push(FieldError::new("keepalive", format!("{:?} is not a number of seconds", self.keepalive)));
+        }
+        if self.served_dir.is_empty() {
+            errors.push(FieldError::new("served_dir", "must be set"));
+        }
+        if self.name_min_len == 0 || self.name_min_len > self.name_max_len {
+            errors.push(FieldError::new("name_min_len", format!("must be between 1 and name_max_len ({})", self.name_max_len)));
+        }
+        errors
+    }
+
+    /// Restores the keys that only take effect on restart from `running`.
+    /// Returns the ones that differed.
+    pub fn keep_restart_only(&mut self, running: &Config) -> Vec<String> {
+        let mut changed = vec![];
+        macro_rules! keep {
+            ($($field:ident),*) => {
+                $(
+                    if self.$field != running.$field {
+                        changed.push(stringify!($field).to_string());
+                        self.$field = running.$field.clone();
+                    }
+                )*
+            };
+        }
+        keep!(addr, stored_file, container, awg_dir, interface, mask, served_dir);
+        if !self.servers.keys().eq(running.servers.keys()) {
+            changed.push("servers".to_string());
+            self.servers = running.servers.clone();
+        }
+        for (name, server) in self.servers.iter_mut() {
+            let running = &running.servers[name];
+            if (&server.container, &server.awg_dir, &server.interface, &server.mask)
+                != (&running.container, &running.awg_dir, &running.interface, &running.mask) {
+                changed.push(format!("servers.{}", name));
+                server.container = running.container.clone();
+                server.awg_dir = running.awg_dir.clone();
+                server.interface = running.interface.clone();
+                server.mask = running.mask.clone();
+            }
+        }
+        changed
+    }
+
+    /// Every server by name: `servers`, or the `default` one made of the top-level keys.
+    pub fn servers(&self) -> BTreeMap<String, ServerConfig> {
+        if !self.servers.is_empty() {
+            return self.servers.clone();
+        }
+        let server = ServerConfig {
+            container: self.container.clone(),
+            awg_dir: self.awg_dir.clone(),
+            interface: self.interface.clone(),
+            host: self.host.clone(),
+            mask: self.mask.clone(),
+            location: self.location.clone(),
+            reserved: self.reserved.clone(),
+        };
+        BTreeMap::from([(DEFAULT_SERVER.to_string(), server)])
+    }
+
+    pub fn server(&self, name: &str) -> Option<ServerConfig> {
+        self.servers().remove(name)
+    }
+
+    /// Location label of server `name`.
+    pub fn location(&self, name: &str) -> String {
+        self.server(name).map(|s| s.location).filter(|l| !l.is_empty()).unwrap_or_else(|| name.to_string())
+    }
+}
+
+static CURRENT: Lazy<RwLock<Arc<Config>>> = Lazy::new(|| {
+    RwLock::new(Arc::new(Config::load(None).unwrap_or_else(|e| panic!("{:#}", e))))
+});
+static SOURCE: OnceCell<Option<PathBuf>> = OnceCell::new();
+
+/// Makes `config`, loaded from `source`, the process-wide config.
+pub fn init(config: Config, source: Option<PathBuf>) {
+    SOURCE.set(source).ok();
+    replace(config);
+}
+
+/// Swaps in `config` for everything that reads [`config`] from now on.
+pub fn replace(config: Config) {
+    *CURRENT.write().unwrap() = Arc::new(config);
+}
+
+/// Loads the config again from the file it was started with and the environment.
+pub fn reload_source() -> Result<Config> {
+    Config::load(SOURCE.get().cloned().flatten().as_deref())
+}
+
+/// The process-wide config. Without [`init`], loaded from the environment alone.
+pub fn config() -> Arc<Config> {
+    CURRENT.read().unwrap().clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn restart_only_keys_are_kept() {
+        let server = ServerConfig { host: "vpn.example.com".to_string(), mask: "10.8.1.".to_string(), ..Default::default() };
+        let running = Config { mask: "10.8.1.".to_string(), servers: BTreeMap::from([("ams".to_string(), server.clone())]), ..Default::default() };
+
+        let mut next = running.clone();
+        next.mask = "10.9.1.".to_string();
+        next.dns = "1.1.1.1".to_string();
+        let moved = ServerConfig { mask: "10.9.2.".to_string(), host: "ams.example.com".to_string(), ..server };
+        next.servers.insert("ams".to_string(), moved);
+        assert_eq!(next.keep_restart_only(&running), ["mask", "servers.ams"]);
+        assert_eq!((next.mask.as_str(), next.servers["ams"].mask.as_str()), ("10.8.1.", "10.8.1."));
+        // Keys that apply at once are taken as they are.
+        assert_eq!((next.dns.as_str(), next.servers["ams"].host.as_str()), ("1.1.1.1", "ams.example.com"));
+    }
+}
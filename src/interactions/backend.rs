@@ -0,0 +1,262 @@
+use std::{collections::{HashMap, HashSet}, sync::Arc};
+
+use anyhow::Result;
+
+#[cfg(any(test, feature = "fake"))]
+use crate::interactions::fake::FakeBackend;
+use crate::{config::{config, ServerConfig}, interactions::{isolation, shared::{command_in_docker, command_in_docker_with_input, read_from_docker, write_to_docker}, wg0::AwgPeer}};
+
+/// Where a server's AmneziaWG interface lives.
+///
+/// `Docker` drives the container of the named server in the config through
+/// `docker exec`/`docker cp`; `Fake` keeps the files and the interface in
+/// memory, for tests; it is only built with the `fake` feature.
+#[derive(Clone)]
+pub enum Backend {
+    Docker { name: Arc<str> },
+    #[cfg(any(test, feature = "fake"))]
+    Fake(Arc<FakeBackend>),
+}
+
+fn checked(o: std::process::Output, what: &str) -> Result<()> {
+    if !o.status.success() {
+        return Err(anyhow::anyhow!("{} failed: {}", what, String::from_utf8_lossy(&o.stderr)));
+    }
+    Ok(())
+}
+
+impl Backend {
+    /// The server `name` of the config.
+    pub fn docker(name: &str) -> Self {
+        Self::Docker { name: name.into() }
+    }
+
+    /// An in-memory `default` server.
+    #[cfg(any(test, feature = "fake"))]
+    pub fn fake() -> Self {
+        Self::Fake(Arc::new(FakeBackend::new()))
+    }
+
+    /// An in-memory server called `name`.
+    #[cfg(any(test, feature = "fake"))]
+    pub fn fake_named(name: &str, server: ServerConfig) -> Self {
+        Self::Fake(Arc::new(FakeBackend::named(name, server)))
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Docker { name } => name,
+            #[cfg(any(test, feature = "fake"))]
+            Self::Fake(f) => &f.name,
+        }
+    }
+
+    /// The server's settings. Read from the running config each time, so a
+    /// reload applies changes to `host` and `mask`; the rest needs a restart.
+    pub fn server(&self) -> ServerConfig {
+        match self {
+            Self::Docker { name } => config().server(name).unwrap_or_else(|| panic!("server {} is not configured", name)),
+            #[cfg(any(test, feature = "fake"))]
+            Self::Fake(f) => f.server.clone(),
+        }
+    }
+
+    pub async fn read_file(&self, path: &str) -> Result<String> {
+        match self {
+            Self::Docker { .. } => read_from_docker(&self.server().container, path).await,
+            #[cfg(any(test, feature = "fake"))]
+            Self::Fake(f) => f.read_file(path),
+        }
+    }
+
+    pub async fn write_file(&self, path: &str, data: &str) -> Result<()> {
+        match self {
+            Self::Docker { .. } => write_to_docker(&self.server().container, data, path).await,
+            #[cfg(any(test, feature = "fake"))]
+            Self::Fake(f) => {
+                f.write_file(path, data);
+                Ok(())
+            }
+        }
+    }
+
+    /// Generates a client keypair and preshared key. Returns `(public, private, psk)`.
+    pub async fn generate_keys(&self) -> Result<(String, String, String)> {
+        #[cfg(any(test, feature = "fake"))]
+        if let Self::Fake(_) = self {
+            return Ok(FakeBackend::generate_keys());
+        }
+        let server = self.server();
+        let script = format!(r#"cd {} \
+            && umask 077 \
+            && wg genkey | tee client.key | wg pubkey > client.pub \
+            && wg genpsk > client.psk \
+            && rm -f /tmp/client \
+            && cat client.pub >> /tmp/client \
+            && cat client.key >> /tmp/client \
+            && cat client.psk >> /tmp/client \
+            && cat /tmp/client \
+            && rm -f /tmp/client && rm -f client.key && rm -f client.pub"#, server.awg_dir);
+        let o = command_in_docker(&server.container, &["bash", "-c", &script]).await?;
+
+        let r = String::from_utf8_lossy(&o.stdout);
+        let mut i = r.split("\n");
+        let public = i.next().filter(|k| !k.is_empty()).ok_or(anyhow::anyhow!("No public key generated"))?;
+        let private = i.next().filter(|k| !k.is_empty()).ok_or(anyhow::anyhow!("No private key generated"))?;
+        let psk = i.next().filter(|k| !k.is_empty()).ok_or(anyhow::anyhow!("No preshared generated"))?;
+        Ok((public.to_string(), private.to_string(), psk.to_string()))
+    }
+
+    /// A fresh preshared key, for peers that bring their own keypair.
+    pub async fn generate_psk(&self) -> Result<String> {
+        #[cfg(any(test, feature = "fake"))]
+        if let Self::Fake(_) = self {
+            return Ok(FakeBackend::generate_keys().2);
+        }
+        let o = command_in_docker(&self.server().container, &["wg", "genpsk"]).await?;
+        let psk = String::from_utf8_lossy(&o.stdout).trim().to_string();
+        if psk.is_empty() {
+            return Err(anyhow::anyhow!("No preshared generated"));
+        }
+        Ok(psk)
+    }
+
+    /// Checks that `wg-quick` accepts the config at `path`.
+    pub async fn check_conf(&self, path: &str) -> Result<()> {
+        match self {
+            Self::Docker { .. } => checked(command_in_docker(&self.server().container, &["wg-quick", "strip", path]).await?, "wg-quick strip"),
+            #[cfg(any(test, feature = "fake"))]
+            Self::Fake(f) => f.check_conf(path),
+        }
+    }
+
+    /// Loads the interface config into the running interface with `wg syncconf`.
+    pub async fn sync_conf(&self) -> Result<()> {
+        match self {
+            Self::Docker { .. } => {
+                let server = self.server();
+                checked(
+                    command_in_docker(&server.container, &["bash", "-c", &format!("wg syncconf {} <(wg-quick strip {})", server.interface, server.wg_conf())]).await?,
+                    "wg syncconf",
+                )
+            }
+            #[cfg(any(test, feature = "fake"))]
+            Self::Fake(f) => f.sync_conf(),
+        }
+    }
+
+    /// Takes the interface down and brings it up again from its config, for
+    /// `[Interface]` changes `wg syncconf` does not apply.
+    pub async fn restart_interface(&self) -> Result<()> {
+        match self {
+            Self::Docker { .. } => {
+                let server = self.server();
+                checked(
+                    command_in_docker(&server.container, &["bash", "-c", &format!("wg-quick down {0} && wg-quick up {0}", server.wg_conf())]).await?,
+                    "wg-quick down/up",
+                )
+            }
+            #[cfg(any(test, feature = "fake"))]
+            Self::Fake(f) => f.restart_interface(),
+        }
+    }
+
+    /// Adds the peer to the running interface or updates it in place.
+    pub async fn set_peer(&self, peer: &AwgPeer) -> Result<()> {
+        match self {
+            Self::Docker { .. } => {
+                let server = self.server();
+                // `wg set` takes the list without the spaces wg0.conf has.
+                let allowed_ips = peer.allowed_ips.replace(' ', "");
+                checked(
+                    command_in_docker_with_input(&server.container,
+                        &["wg", "set", &server.interface, "peer", &peer.public_key, "preshared-key", "/dev/stdin", "allowed-ips", &allowed_ips],
+                        &peer.preshared_key,
+                    ).await?,
+                    "wg set",
+                )
+            }
+            #[cfg(any(test, feature = "fake"))]
+            Self::Fake(f) => f.set_peer(&peer.public_key),
+        }
+    }
+
+    /// Removes the peer from the running interface.
+    pub async fn remove_peer(&self, public_key: &str) -> Result<()> {
+        match self {
+            Self::Docker { .. } => {
+                let server = self.server();
+                checked(
+                    command_in_docker(&server.container, &["wg", "set", &server.interface, "peer", public_key, "remove"]).await?,
+                    "wg set remove",
+                )
+            }
+            #[cfg(any(test, feature = "fake"))]
+            Self::Fake(f) => f.remove_peer(public_key),
+        }
+    }
+
+    /// Runs the isolation rules `script`, then saves it next to the interface
+    /// config. A script that fails is not saved, so the saved one is always
+    /// the last that loaded.
+    pub async fn load_rules(&self, script: &str) -> Result<()> {
+        match self {
+            Self::Docker { .. } => checked(command_in_docker_with_input(&self.server().container, &["bash", "-s"], script).await?, "isolation rules")?,
+            #[cfg(any(test, feature = "fake"))]
+            Self::Fake(f) => f.load_rules(script)?,
+        }
+        self.write_file(&self.server().isolation_script(), script).await
+    }
+
+    /// The rules in the isolation chain of the running firewall, as `iptables -S`
+    /// lists them. None if the chain is missing, e.g. after the container restarted.
+    pub async fn live_rules(&self) -> Result<Option<Vec<String>>> {
+        match self {
+            Self::Docker { .. } => {
+                let o = command_in_docker(&self.server().container, &["iptables", "-S", isolation::CHAIN]).await?;
+                if !o.status.success() {
+                    return Ok(None);
+                }
+                let prefix = format!("-A {} ", isolation::CHAIN);
+                Ok(Some(String::from_utf8_lossy(&o.stdout).lines().filter(|l| l.starts_with(&prefix)).map(str::to_string).collect()))
+            }
+            #[cfg(any(test, feature = "fake"))]
+            Self::Fake(f) => Ok(f.live_rules()),
+        }
+    }
+
+    /// Public keys of the peers currently loaded into the running interface.
+    pub async fn live_peers(&self) -> Result<HashSet<String>> {
+        match self {
+            Self::Docker { .. } => {
+                let server = self.server();
+                let o = command_in_docker(&server.container, &["wg", "show", &server.interface, "peers"]).await?;
+                let out = String::from_utf8_lossy(&o.stdout).into_owned();
+                checked(o, "wg show")?;
+                Ok(out.lines().map(|l| l.trim().to_string()).filter(|l| !l.is_empty()).collect())
+            }
+            #[cfg(any(test, feature = "fake"))]
+            Self::Fake(f) => Ok(f.live_peers()),
+        }
+    }
+
+    /// Unix time of each peer's last handshake. Peers that never completed
+    /// one are left out.
+    pub async fn latest_handshakes(&self) -> Result<HashMap<String, i64>> {
+        match self {
+            Self::Docker { .. } => {
+                let server = self.server();
+                let o = command_in_docker(&server.container, &["wg", "show", &server.interface, "latest-handshakes"]).await?;
+                let out = String::from_utf8_lossy(&o.stdout).into_owned();
+                checked(o, "wg show")?;
+                Ok(out.lines()
+                    .filter_map(|l| l.split_once('\t'))
+                    .filter_map(|(key, time)| Some((key.trim().to_string(), time.trim().parse().ok()?)))
+                    .filter(|(_, time)| *time > 0)
+                    .collect())
+            }
+            #[cfg(any(test, feature = "fake"))]
+            Self::Fake(f) => Ok(f.latest_handshakes()),
+        }
+    }
+}
@@ -0,0 +1,149 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use tokio::{sync::{mpsc, oneshot}, time::Instant};
+use tracing::info;
+
+use crate::interactions::{jobs::CreateRequest, shared::{AppState, GroupRecord}, validation::Site};
+
+/// How long the writer waits for more operations after the first one arrives.
+const BATCH_WINDOW: Duration = Duration::from_millis(25);
+/// Upper bound on operations applied in one container write.
+const BATCH_LIMIT: usize = 256;
+
+pub enum UserOp {
+    /// `server` unset means the group's pinned server, or one picked by the
+    /// placement policy. With `link`, the peer belongs to an existing user
+    /// rather than being a user of its own.
+    /// With `public_key`, the user brought its own keypair; with `address`, it gets that one.
+    Create { name: String, group: String, server: Option<String>, link: Option<Link>, public_key: Option<String>, address: Option<String>, site: Option<Site> },
+    Delete { client_id: String },
+    /// Renames the user and/or moves its config to another group. `device_limit`
+    /// only applies to users, not to their further devices or locations.
+    Update { client_id: String, name: Option<String>, group: Option<String>, device_limit: Option<usize> },
+    /// Comments the peer out of `wg0.conf` and takes it off the interface,
+    /// keeping its address and config.
+    Suspend { client_id: String },
+    /// Puts a suspended peer back.
+    Resume { client_id: String },
+}
+
+/// How a created peer belongs to an existing user, by the user's id.
+#[derive(Clone)]
+pub enum Link {
+    /// A config of the user on a further server.
+    Location(String),
+    /// A further device of the user, on the user's server.
+    Device { user: String, name: String },
+}
+
+pub enum OpOutcome {
+    Created { id: String, record: GroupRecord, server: String },
+    Deleted,
+    Updated,
+}
+
+/// Operations queued together, answered together.
+struct Pending {
+    ops: Vec<UserOp>,
+    reply: oneshot::Sender<Vec<Result<OpOutcome>>>,
+}
+
+/// Queues single-user create/delete operations so that the ones arriving
+/// within `BATCH_WINDOW` share one parse-modify-write-sync cycle.
+#[derive(Clone)]
+pub struct Batcher {
+    tx: mpsc::UnboundedSender<Pending>,
+}
+
+/// Receiving end of a `Batcher`, turned into the writer task by `spawn`.
+pub struct BatchQueue {
+    rx: mpsc::UnboundedReceiver<Pending>,
+}
+
+impl BatchQueue {
+    pub fn spawn(self, state: AppState) {
+        tokio::spawn(run(state, self.rx));
+    }
+}
+
+impl Batcher {
+    pub fn new() -> (Self, BatchQueue) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        (Self { tx }, BatchQueue { rx })
+    }
+
+    pub async fn submit(&self, op: UserOp) -> Result<OpOutcome> {
+        self.submit_all(vec![op]).await?.pop().unwrap_or_else(|| Err(anyhow::anyhow!("Batch writer dropped the operation")))
+    }
+
+    /// Queues `ops` so that they land in the same batch: the ones on one
+    /// server are applied in one transaction. Returns their outcomes in order.
+    pub async fn submit_all(&self, ops: Vec<UserOp>) -> Result<Vec<Result<OpOutcome>>> {
+        if ops.is_empty() {
+            return Ok(vec![]);
+        }
+        let (reply, rx) = oneshot::channel();
+        self.tx.send(Pending { ops, reply }).map_err(|_| anyhow::anyhow!("Batch writer is gone"))?;
+        rx.await.map_err(|_| anyhow::anyhow!("Batch writer dropped the operations"))
+    }
+
+    /// Creates the user of `req`, then its configs on `req.locations`. If one
+    /// of those fails, the configs created so far are deleted again.
+    pub async fn create(&self, req: CreateRequest) -> Result<OpOutcome> {
+        let CreateRequest { name, group, server, locations, public_key, address, site } = req;
+        let outcome = self.submit(UserOp::Create { name: name.clone(), group: group.clone(), server, link: None, public_key, address, site }).await?;
+        let OpOutcome::Created { id, server, .. } = &outcome else { return Ok(outcome) };
+        let mut servers = vec![server.clone()];
+        let mut created = vec![id.clone()];
+        for location in locations {
+            if servers.contains(&location) {
+                continue;
+            }
+            let op = UserOp::Create { name: name.clone(), group: group.clone(), server: Some(location.clone()), link: Some(Link::Location(id.clone())), public_key: None, address: None, site: None };
+            match self.submit(op).await {
+                Ok(OpOutcome::Created { id, .. }) => {
+                    created.push(id);
+                    servers.push(location);
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    for client_id in created.into_iter().rev() {
+                        self.submit(UserOp::Delete { client_id }).await.ok();
+                    }
+                    return Err(e.context(format!("Failed to create the config on {}", location)));
+                }
+            }
+        }
+        Ok(outcome)
+    }
+}
+
+async fn run(state: AppState, mut rx: mpsc::UnboundedReceiver<Pending>) {
+    while let Some(first) = rx.recv().await {
+        let mut count = first.ops.len();
+        let mut pending = vec![first];
+        let deadline = Instant::now() + BATCH_WINDOW;
+        while count < BATCH_LIMIT {
+            match tokio::time::timeout_at(deadline, rx.recv()).await {
+                Ok(Some(p)) => {
+                    count += p.ops.len();
+                    pending.push(p);
+                }
+                _ => break,
+            }
+        }
+        info!("Applying {} batched operation(s)", count);
+
+        let mut ops = vec![];
+        let mut replies = vec![];
+        for p in pending {
+            replies.push((p.reply, p.ops.len()));
+            ops.extend(p.ops);
+        }
+        let mut results = state.run_ops(ops).await.into_iter();
+        for (reply, n) in replies {
+            reply.send(results.by_ref().take(n).collect()).ok();
+        }
+    }
+}
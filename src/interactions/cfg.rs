@@ -1,35 +1,54 @@
-use std::{collections::HashMap, path::PathBuf};
+use std::collections::{HashMap, HashSet};
 
-use handlebars::Handlebars;
-use once_cell::sync::OnceCell;
 use serde::{Deserialize, Serialize};
 use anyhow::{Ok, Result};
-use tokio::fs;
-use tracing::{info, warn};
+use tracing::info;
 use chrono::prelude::*;
 
-use crate::{interactions::{client_table::{get_client_table_from_docker, ClientTableRecord, ClientTableRecordUserData}, shared::{command_in_docker, copy_to_docker, shred, sync_wg, write_to_docker}, wg0::{AWGInterfaceData,  AwgInterfaceConf, AwgPeer}}, ENV};
+use simple_awg_api_types::{AwgCompat, Site};
 
+use crate::{interactions::{error::AwgError, validation::FieldError, client_table::{ClientTableRecord, ClientTableRecordUserData}, templates, transaction::Transaction, wg0::{AwgInterfaceConf, AwgPeer}}, config::{config, ServerConfig}};
 
 
 
 
 
-pub async fn rm_by_id(client_id: &str) -> Result<()> {
-    let mut clients_table = get_client_table_from_docker().await?;
-    clients_table.retain(|c| c.client_id != client_id);
-    let Some(mut wg_conf) = AwgInterfaceConf::from_docker().await? else {
-        return Err(anyhow::anyhow!("Failed to parse wg0.conf"));
-    };
-    wg_conf.peers.remove(client_id);
-    write_to_docker(&serde_json::to_string_pretty(&clients_table)?, "/opt/amnezia/awg/clientsTable").await?;
-    write_to_docker(&wg_conf.to_string(), "/opt/amnezia/awg/wg0.conf").await?;
-    sync_wg().await?;
 
-    Ok(())
+pub fn rm_by_id(tx: &mut Transaction, client_id: &str) {
+    tx.clients_table.retain(|c| c.client_id != client_id);
+    tx.wg.peers.remove(client_id);
+    tx.wg.suspended.remove(client_id);
 }
 
 
+/// Put in place of the private key of peers that brought their own keypair.
+pub const PRIVATE_KEY_PLACEHOLDER: &str = "<your private key>";
+
+/// What a client config is rebuilt from: everything that isn't a server setting.
+/// `private_key` is empty for peers that brought their own keypair.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ClientKeys {
+    pub address: String,
+    pub private_key: String,
+    pub preshared_key: String,
+}
+
+impl ClientKeys {
+    /// Recovers the keys from a rendered config, for users stored before keys were.
+    pub fn parse(config: &str) -> Option<Self> {
+        let value = |key: &str| config.lines()
+            .filter_map(|l| l.split_once('='))
+            .find(|(k, _)| k.trim() == key)
+            .map(|(_, v)| v.trim().to_string())
+            .filter(|v| !v.is_empty());
+        Some(Self {
+            address: value("Address")?.trim_end_matches("/32").to_string(),
+            private_key: value("PrivateKey").filter(|k| k != PRIVATE_KEY_PLACEHOLDER).unwrap_or_default(),
+            preshared_key: value("PresharedKey")?,
+        })
+    }
+}
+
 #[derive(Serialize)]
 pub struct ClientConfig {
     pub addr: String,
@@ -44,6 +63,19 @@ pub struct ClientConfig {
     pub h2: String,
     pub h3: String,
     pub h4: String,
+    // Only set for AmneziaWG 1.5/2.0 servers and `AwgCompat::Full` groups;
+    // the template leaves out the ones that are unset.
+    pub s3: Option<String>,
+    pub s4: Option<String>,
+    pub i1: Option<String>,
+    pub i2: Option<String>,
+    pub i3: Option<String>,
+    pub i4: Option<String>,
+    pub i5: Option<String>,
+    pub j1: Option<String>,
+    pub j2: Option<String>,
+    pub j3: Option<String>,
+    pub itime: Option<String>,
 
     pub peer_public_key: String,
     pub peer_preshared_key: String,
@@ -54,11 +86,68 @@ pub struct ClientConfig {
 
 
 impl ClientConfig {
-    fn render(&self) -> Result<String> {
-        let mut handlebars = Handlebars::new();
-        handlebars.register_template_file("config", "data/templates/config.hbs")?;
-        let r = handlebars.render("config",self);
-        Ok(r?)
+    fn new(server: &ServerConfig, wg: &AwgInterfaceConf, addr: String, private_key: String, psk: String, compat: AwgCompat) -> Self {
+        let config = config();
+        let iface = &wg.parsed_iface;
+        let full = compat == AwgCompat::Full;
+        // 1.0 clients take a single H value; any value in the server's range is accepted.
+        let header = |h: &String| match compat {
+            AwgCompat::Full => h.clone(),
+            AwgCompat::Legacy => h.split('-').next().unwrap_or_default().trim().to_string(),
+        };
+        let extended = |v: &Option<String>| v.clone().filter(|_| full);
+        Self {
+            addr,
+            dns: config.dns.clone(),
+            private_key: if private_key.is_empty() { PRIVATE_KEY_PLACEHOLDER.to_string() } else { private_key },
+            jc: iface.jc.clone(),
+            jmin: iface.jmin.clone(),
+            jmax: iface.jmax.clone(),
+            s1: iface.s1.clone(),
+            s2: iface.s2.clone(),
+            h1: header(&iface.h1),
+            h2: header(&iface.h2),
+            h3: header(&iface.h3),
+            h4: header(&iface.h4),
+            s3: extended(&iface.s3),
+            s4: extended(&iface.s4),
+            i1: extended(&iface.i1),
+            i2: extended(&iface.i2),
+            i3: extended(&iface.i3),
+            i4: extended(&iface.i4),
+            i5: extended(&iface.i5),
+            j1: extended(&iface.j1),
+            j2: extended(&iface.j2),
+            j3: extended(&iface.j3),
+            itime: extended(&iface.itime),
+            peer_public_key: wg.public_key.clone(),
+            peer_preshared_key: psk,
+
+            peer_allowed_ips: "0.0.0.0/0, ::/0".to_string(),
+            peer_endpoint: format!("{}:{}", server.host, wg.parsed_iface.port),
+            peer_persistent_keepalive: config.keepalive.clone()
+        }
+    }
+
+    /// The config for `keys` with the current settings of `server`, as `compat` clients read it.
+    pub fn from_keys(server: &ServerConfig, wg: &AwgInterfaceConf, keys: &ClientKeys, compat: AwgCompat) -> Self {
+        Self::new(server, wg, keys.address.clone(), keys.private_key.clone(), keys.preshared_key.clone(), compat)
+    }
+
+    /// Adjusts the config for a site's router: no DNS, and the site's
+    /// `AllowedIPs` if it narrows them.
+    pub fn for_site(mut self, site: Option<&Site>) -> Self {
+        if let Some(site) = site {
+            self.dns = String::new();
+            if !site.allowed_ips.is_empty() {
+                self.peer_allowed_ips = site.allowed_ips.join(", ");
+            }
+        }
+        self
+    }
+
+    pub fn render(&self) -> Result<String> {
+        templates::render(templates::CONFIG, self)
     }
 
     fn to_record(&self, name: String, public: String) -> ClientTableRecord {
@@ -86,150 +175,121 @@ impl ClientConfig {
     }
 }
 
-pub async fn drop_all() -> Result<()> {
-    let clients_table: Vec<ClientTableRecord> = Vec::new();
-    let Some(mut wg_conf) = AwgInterfaceConf::from_docker().await? else {
-        return Err(anyhow::anyhow!("Failed to parse wg0.conf"));
-    };
-    wg_conf.peers = HashMap::new();
-    write_to_docker(&serde_json::to_string_pretty(&clients_table)?, "/opt/amnezia/awg/clientsTable").await?;
-    write_to_docker(&wg_conf.to_string(), "/opt/amnezia/awg/wg0.conf").await?;
-    sync_wg().await?;
-    Ok(())
+pub fn drop_all(tx: &mut Transaction) {
+    tx.clients_table = Vec::new();
+    tx.wg.peers = HashMap::new();
+    tx.wg.suspended = HashMap::new();
 }
 
 
-
-pub async fn create_user(name: &str) -> Result<(String, String)> {
-    let mut wg = AwgInterfaceConf::from_docker().await?
-        .ok_or(anyhow::anyhow!("Failed to parse wg0.conf"))?;
-    info!("Wg interface: {}", wg.interface);
-
-    let mut clients_table = get_client_table_from_docker().await?;
-
-    let _o = command_in_docker(
-        &[
-            "bash", "-c", r#"cd /opt/amnezia/awg \
-            && umask 077 \
-            && wg genkey | tee client.key | wg pubkey > client.pub \
-            && wg genpsk > client.psk \
-            && rm -f /tmp/client \
-            && cat client.pub >> /tmp/client \
-            && cat client.key >> /tmp/client \
-            && cat client.psk >> /tmp/client \
-            && cat /tmp/client \
-            && rm -f /tmp/client && rm -f client.key && rm -f client.pub"#
-        ]
-    ).await?;
-    
-    info!("Res: {:?}", String::from_utf8_lossy(&_o.stderr));
-    let r = String::from_utf8_lossy(&_o.stdout);
-    let mut i = r.split("\n");
-    let public = i.next().ok_or(anyhow::anyhow!("No public key generated"))?;
-    let private = i.next().ok_or(anyhow::anyhow!("No private key generated"))?;
-    let psk = i.next().ok_or(anyhow::anyhow!("No preshared generated"))?;
-
-    let id = wg.get_last_id() + 1;
-
-    let cfg = ClientConfig {
-        addr: format!("{}{}", ENV.mask, id),
-        dns: ENV.dns.clone(),
-        private_key: private.to_string(),
-        jc: wg.parsed_iface.jc.clone(),
-        jmin: wg.parsed_iface.jmin.clone(),
-        jmax: wg.parsed_iface.jmax.clone(),
-        s1: wg.parsed_iface.s1.clone(),
-        s2: wg.parsed_iface.s2.clone(),
-        h1: wg.parsed_iface.h1.clone(),
-        h2: wg.parsed_iface.h2.clone(),
-        h3: wg.parsed_iface.h3.clone(),
-        h4: wg.parsed_iface.h4.clone(),
-        peer_public_key: wg.public_key.clone(),
-        peer_preshared_key: psk.to_string(),
-
-        peer_allowed_ips: ENV.host.clone(),
-        peer_endpoint: format!("{}:{}", ENV.host, wg.parsed_iface.port),
-        peer_persistent_keepalive: ENV.keepalive.clone()
-    };
-    let rendered = cfg.render()?;
-    let peer = cfg.to_peer(public.to_string());
-    let record = cfg.to_record(name.to_string(), public.to_string());
-    wg.peers.insert(public.to_string(), peer);
-    clients_table.push(record);
-
-
-    write_to_docker(&serde_json::to_string_pretty(&clients_table)?, "/opt/amnezia/awg/clientsTable").await?;
-    write_to_docker(&wg.to_string(), "/opt/amnezia/awg/wg0.conf").await?;
-    sync_wg().await?;
-    info!("Created user: {}", name);
-    Ok((public.to_string(), rendered))
+/// A peer to add with [`create_users`].
+pub struct NewPeer {
+    pub name: String,
+    pub compat: AwgCompat,
+    /// Brought by the user; no keypair is generated then.
+    pub public_key: Option<String>,
+    /// Fixed address; otherwise the lowest free one that isn't reserved.
+    pub address: Option<String>,
+    /// Its routes are added to the peer's `AllowedIPs` on the server.
+    pub site: Option<Site>,
 }
 
+/// Adds every peer in `users` to the transaction. Returns `(public key, keys, rendered config)`
+/// in the same order as `users`; nothing is written until the transaction is applied.
+pub async fn create_users(tx: &mut Transaction, users: &[NewPeer]) -> Result<Vec<(String, ClientKeys, String)>> {
+    info!("Wg interface: {}", tx.wg.interface);
+    let server = tx.backend.server();
+    // Fixed addresses of the batch are kept free for their own peers.
+    let skip: HashSet<u32> = server.reserved_ids().into_iter()
+        .chain(users.iter().filter_map(|u| u.address.as_deref().and_then(|a| server.host_id(a))))
+        .collect();
+    let mut out = Vec::with_capacity(users.len());
+    for NewPeer { name, compat, public_key, address, site } in users {
+        let (public, private, psk) = match public_key {
+            Some(public) => (public.clone(), String::new(), tx.backend.generate_psk().await?),
+            None => tx.backend.generate_keys().await?,
+        };
 
-pub async fn create_users(names: &Vec<String>) -> Result<Vec<(String, String)>> {
-    let mut wg = AwgInterfaceConf::from_docker().await?
-        .ok_or(anyhow::anyhow!("Failed to parse wg0.conf"))?;
-    info!("Wg interface: {}", wg.interface);
-
-    let mut clients_table = get_client_table_from_docker().await?;
-    let mut out = Vec::with_capacity(names.len());
-    for name in names {
-        let _o = command_in_docker(
-            &[
-                "bash", "-c", r#"cd /opt/amnezia/awg \
-                && umask 077 \
-                && wg genkey | tee client.key | wg pubkey > client.pub \
-                && wg genpsk > client.psk \
-                && rm -f /tmp/client \
-                && cat client.pub >> /tmp/client \
-                && cat client.key >> /tmp/client \
-                && cat client.psk >> /tmp/client \
-                && cat /tmp/client \
-                && rm -f /tmp/client && rm -f client.key && rm -f client.pub"#
-            ]
-        ).await?;
-        
-        info!("Res: {:?}", String::from_utf8_lossy(&_o.stderr));
-        let r = String::from_utf8_lossy(&_o.stdout);
-        let mut i = r.split("\n");
-        let public = i.next().ok_or(anyhow::anyhow!("No public key generated"))?;
-        let private = i.next().ok_or(anyhow::anyhow!("No private key generated"))?;
-        let psk = i.next().ok_or(anyhow::anyhow!("No preshared generated"))?;
-
-        let id = wg.get_last_id() + 1;
-
-        let cfg = ClientConfig {
-            addr: format!("{}{}", ENV.mask, id),
-            dns: ENV.dns.clone(),
-            private_key: private.to_string(),
-            jc: wg.parsed_iface.jc.clone(),
-            jmin: wg.parsed_iface.jmin.clone(),
-            jmax: wg.parsed_iface.jmax.clone(),
-            s1: wg.parsed_iface.s1.clone(),
-            s2: wg.parsed_iface.s2.clone(),
-            h1: wg.parsed_iface.h1.clone(),
-            h2: wg.parsed_iface.h2.clone(),
-            h3: wg.parsed_iface.h3.clone(),
-            h4: wg.parsed_iface.h4.clone(),
-            peer_public_key: wg.public_key.clone(),
-            peer_preshared_key: psk.to_string(),
-
-            peer_allowed_ips: ENV.host.clone(),
-            peer_endpoint: format!("{}:{}", ENV.host, wg.parsed_iface.port),
-            peer_persistent_keepalive: ENV.keepalive.clone()
+        let id = match address {
+            Some(address) => {
+                let id = server.host_id(address)
+                    .ok_or_else(|| AwgError::Validation(vec![FieldError::new("address", format!("is not in the subnet {}0/24", server.mask))]))?;
+                if tx.wg.used_ids().contains(&id) {
+                    return Err(AwgError::Conflict(format!("Address {} is already taken", address)).into());
+                }
+                id
+            }
+            None => tx.wg.free_id(&skip).ok_or(AwgError::SubnetExhausted)?,
         };
+        if tx.wg.has_peer(&public) {
+            return Err(AwgError::Conflict(format!("Peer {} already exists", public)).into());
+        }
+
+        let keys = ClientKeys { address: format!("{}{}", server.mask, id), private_key: private, preshared_key: psk };
+        let cfg = ClientConfig::from_keys(&server, &tx.wg, &keys, *compat).for_site(site.as_ref());
         let rendered = cfg.render()?;
-        let peer = cfg.to_peer(public.to_string());
-        let record = cfg.to_record(name.to_string(), public.to_string());
-        wg.peers.insert(public.to_string(), peer);
-        clients_table.push(record);
-        out.push((name.to_string(), rendered));
+        let mut peer = cfg.to_peer(public.clone());
+        if let Some(site) = site {
+            peer.allowed_ips = [peer.allowed_ips].into_iter().chain(site.routes.iter().cloned()).collect::<Vec<_>>().join(", ");
+        }
+        let record = cfg.to_record(name.to_string(), public.clone());
+        tx.wg.peers.insert(public.clone(), peer);
+        tx.clients_table.push(record);
+        out.push((public, keys, rendered));
         info!("Created user: {}", name);
     }
-
-    write_to_docker(&serde_json::to_string_pretty(&clients_table)?, "/opt/amnezia/awg/clientsTable").await?;
-    write_to_docker(&wg.to_string(), "/opt/amnezia/awg/wg0.conf").await?;
-    sync_wg().await?;
-    info!("Synced!");
     Ok(out)
 }
+
+/// Swaps peer `client_id` in the transaction for one with a fresh keypair and
+/// PSK, at the same address and with its `clientsTable` record moved over.
+/// Peers that brought their own keypair only get a new PSK and keep their key.
+/// Returns `(public key, keys, rendered config)`.
+pub async fn rotate_keys(tx: &mut Transaction, client_id: &str, old: &ClientKeys, compat: AwgCompat, site: Option<&Site>) -> Result<(String, ClientKeys, String)> {
+    let peer = tx.wg.peers.remove(client_id)
+        .ok_or_else(|| AwgError::NotFound(format!("Peer {} in wg0.conf", client_id)))?;
+    let (public, private, psk) = if old.private_key.is_empty() {
+        (client_id.to_string(), String::new(), tx.backend.generate_psk().await?)
+    } else {
+        tx.backend.generate_keys().await?
+    };
+    if tx.wg.has_peer(&public) {
+        return Err(AwgError::Conflict(format!("Peer {} already exists", public)).into());
+    }
+    let keys = ClientKeys { address: old.address.clone(), private_key: private, preshared_key: psk };
+    let rendered = ClientConfig::from_keys(&tx.backend.server(), &tx.wg, &keys, compat).for_site(site).render()?;
+    tx.wg.peers.insert(public.clone(), AwgPeer { public_key: public.clone(), preshared_key: keys.preshared_key.clone(), ..peer });
+    if let Some(record) = tx.clients_table.iter_mut().find(|c| c.client_id == client_id) {
+        record.client_id = public.clone();
+    }
+    info!("Rotated keys of {}", client_id);
+    Ok((public, keys, rendered))
+}
+
+/// Lines removed (`-`) and added (`+`) going from `old` to `new`. Unchanged
+/// lines are left out, so a diff of two configs with the same keys never
+/// contains them.
+pub fn line_diff(old: &str, new: &str) -> String {
+    let a: Vec<&str> = old.lines().collect();
+    let b: Vec<&str> = new.lines().collect();
+    let mut lcs = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = if a[i] == b[j] { lcs[i + 1][j + 1] + 1 } else { lcs[i + 1][j].max(lcs[i][j + 1]) };
+        }
+    }
+    let (mut i, mut j, mut out) = (0, 0, String::new());
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            i += 1;
+            j += 1;
+        } else if i < a.len() && (j == b.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            out += &format!("-{}\n", a[i]);
+            i += 1;
+        } else {
+            out += &format!("+{}\n", b[j]);
+            j += 1;
+        }
+    }
+    out
+}
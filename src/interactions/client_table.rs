@@ -1,8 +1,6 @@
-use std::fmt::Result;
-
 use serde::{Deserialize, Serialize};
 
-use crate::interactions::shared::command_in_docker;
+use crate::interactions::{backend::Backend, error::AwgError};
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct ClientTableRecord {
     #[serde(rename = "clientId")]
@@ -28,10 +26,36 @@ pub struct ClientTableRecordUserData {
     pub allowed_ips: Option<String>,
 }
 
+impl ClientTableRecordUserData {
+    /// Bytes received and sent, as far as `clientsTable` reports them.
+    pub fn transfer(&self) -> (u64, u64) {
+        let bytes = |v: &Option<String>| v.as_deref().and_then(parse_size).unwrap_or(0);
+        (bytes(&self.data_received), bytes(&self.data_sent))
+    }
+}
+
+/// Bytes in a `clientsTable` transfer value such as `1.25 MiB`.
+pub fn parse_size(s: &str) -> Option<u64> {
+    let (n, unit) = s.trim().split_once(' ').unwrap_or((s.trim(), "B"));
+    let n: f64 = n.parse().ok()?;
+    let scale: u64 = match unit {
+        "B" => 1,
+        "KiB" => 1 << 10,
+        "MiB" => 1 << 20,
+        "GiB" => 1 << 30,
+        "TiB" => 1 << 40,
+        _ => return None,
+    };
+    Some((n * scale as f64) as u64)
+}
 
-pub async fn get_client_table_from_docker() -> anyhow::Result<Vec<ClientTableRecord>> {
-    let output = command_in_docker(&["cat", "/opt/amnezia/awg/clientsTable"]).await?;
-    let data = String::from_utf8_lossy(&output.stdout);
-    let clients: Vec<ClientTableRecord> = serde_json::from_str(&data)?;
+pub async fn get_client_table(backend: &Backend) -> anyhow::Result<Vec<ClientTableRecord>> {
+    let data = backend.read_file(&backend.server().clients_table()).await?;
+    parse_client_table(&data)
+}
+
+pub fn parse_client_table(data: &str) -> anyhow::Result<Vec<ClientTableRecord>> {
+    let clients: Vec<ClientTableRecord> = serde_json::from_str(data)
+        .map_err(|e| AwgError::ConfigParse(format!("clientsTable: {}", e)))?;
     Ok(clients)
-} 
\ No newline at end of file
+}
@@ -0,0 +1,52 @@
+use std::fmt;
+
+use crate::interactions::validation::FieldError;
+
+/// Failures the API reports with a specific kind instead of a generic 500.
+///
+/// Raised through `anyhow` like everything else; `api::error::ApiError` looks for
+/// it in the error chain to pick the status code.
+#[derive(Debug, Clone)]
+pub enum AwgError {
+    /// `docker` could not be run or the container is missing/stopped.
+    ContainerUnreachable(String),
+    /// `wg0.conf` or `clientsTable` could not be parsed.
+    ConfigParse(String),
+    /// No free address left in the interface subnet.
+    SubnetExhausted,
+    NotFound(String),
+    Validation(Vec<FieldError>),
+    Conflict(String),
+}
+
+impl fmt::Display for AwgError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ContainerUnreachable(m) => write!(f, "Container unreachable: {}", m),
+            Self::ConfigParse(m) => write!(f, "Failed to parse {}", m),
+            Self::SubnetExhausted => write!(f, "No free addresses left in the subnet"),
+            Self::NotFound(m) => write!(f, "{} not found", m),
+            Self::Validation(fields) => write!(f, "Invalid {}", fields.iter().map(|e| format!("{}: {}", e.field, e.message)).collect::<Vec<_>>().join("; ")),
+            Self::Conflict(m) => write!(f, "{}", m),
+        }
+    }
+}
+
+impl std::error::Error for AwgError {}
+
+impl AwgError {
+    /// Finds the typed error in an `anyhow` chain, if any.
+    pub fn find(e: &anyhow::Error) -> Option<&AwgError> {
+        e.chain().find_map(|c| c.downcast_ref::<AwgError>())
+    }
+
+    /// A copy of `e` for one of several callers it failed, keeping the typed
+    /// error so each still gets the right status code.
+    pub fn replicate(e: &anyhow::Error) -> anyhow::Error {
+        let msg = format!("{:#}", e);
+        match Self::find(e) {
+            Some(k) => anyhow::Error::new(k.clone()).context(msg),
+            None => anyhow::anyhow!("{}", msg),
+        }
+    }
+}
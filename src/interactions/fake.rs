@@ -0,0 +1,156 @@
+use std::{collections::{HashMap, HashSet}, sync::Mutex};
+
+use anyhow::Result;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use uuid::Uuid;
+
+use crate::{config::{config, ServerConfig, DEFAULT_SERVER}, interactions::wg0::AwgInterfaceConf};
+
+/// In-memory stand-in for an AmneziaWG container: files live in a map and the
+/// "running interface" is the set of peer keys loaded by sync or `wg set`.
+pub struct FakeBackend {
+    pub name: String,
+    pub server: ServerConfig,
+    files: Mutex<HashMap<String, String>>,
+    live: Mutex<HashSet<String>>,
+    /// Unix time of each peer's last handshake, as `wg show latest-handshakes` reports it.
+    handshakes: Mutex<HashMap<String, i64>>,
+    /// Rules of the isolation chain, as the scripts loaded them.
+    rules: Mutex<Option<Vec<String>>>,
+    /// How often each container command ran, by name.
+    calls: Mutex<HashMap<&'static str, usize>>,
+    /// Commands whose next run fails, see `fail_next`.
+    failing: Mutex<HashSet<&'static str>>,
+}
+
+fn random_key() -> String {
+    let mut bytes = [0u8; 32];
+    bytes[..16].copy_from_slice(Uuid::new_v4().as_bytes());
+    bytes[16..].copy_from_slice(Uuid::new_v4().as_bytes());
+    STANDARD.encode(bytes)
+}
+
+impl Default for FakeBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FakeBackend {
+    /// The `default` server of the config, with an empty interface and client table.
+    pub fn new() -> Self {
+        Self::named(DEFAULT_SERVER, config().server(DEFAULT_SERVER).unwrap_or_default())
+    }
+
+    /// A server called `name` with an empty interface and client table.
+    pub fn named(name: &str, server: ServerConfig) -> Self {
+        let wg_conf = format!(
+            "[Interface]\nPrivateKey = {}\nAddress = 10.8.1.0/24\nListenPort = 51820\nJc = 4\nJmin = 10\nJmax = 50\nS1 = 64\nS2 = 97\nH1 = 1403243210\nH2 = 2104328710\nH3 = 3042387650\nH4 = 4012387640\n\n",
+            random_key()
+        );
+        let files = HashMap::from([
+            (server.wg_conf(), wg_conf),
+            (server.clients_table(), "[]".to_string()),
+            (server.server_public_key(), random_key()),
+        ]);
+        Self { name: name.to_string(), server, files: Mutex::new(files), live: Mutex::new(HashSet::new()), handshakes: Mutex::new(HashMap::new()), rules: Mutex::default(), calls: Mutex::default(), failing: Mutex::default() }
+    }
+
+    pub fn read_file(&self, path: &str) -> Result<String> {
+        self.files.lock().unwrap().get(path).cloned().ok_or(anyhow::anyhow!("Failed to read {}: no such file", path))
+    }
+
+    pub fn write_file(&self, path: &str, data: &str) {
+        self.files.lock().unwrap().insert(path.to_string(), data.to_string());
+    }
+
+    pub fn generate_keys() -> (String, String, String) {
+        (random_key(), random_key(), random_key())
+    }
+
+    /// Makes the next run of command `op` (`check_conf`, `sync_conf`,
+    /// `set_peer`, ...) fail, as a broken container would.
+    pub fn fail_next(&self, op: &'static str) {
+        self.failing.lock().unwrap().insert(op);
+    }
+
+    /// How often command `op` ran, failed runs included.
+    pub fn calls(&self, op: &str) -> usize {
+        self.calls.lock().unwrap().get(op).copied().unwrap_or(0)
+    }
+
+    fn run(&self, op: &'static str) -> Result<()> {
+        *self.calls.lock().unwrap().entry(op).or_default() += 1;
+        if self.failing.lock().unwrap().remove(op) {
+            return Err(anyhow::anyhow!("{} failed", op));
+        }
+        Ok(())
+    }
+
+    pub fn check_conf(&self, path: &str) -> Result<()> {
+        self.run("check_conf")?;
+        AwgInterfaceConf::parse(&self.read_file(path)?, String::new())?
+            .ok_or(anyhow::anyhow!("wg-quick strip failed: no [Interface] in {}", path))?;
+        Ok(())
+    }
+
+    pub fn sync_conf(&self) -> Result<()> {
+        self.run("sync_conf")?;
+        self.load_conf()
+    }
+
+    pub fn restart_interface(&self) -> Result<()> {
+        self.run("restart_interface")?;
+        self.load_conf()
+    }
+
+    /// Makes the peers of the interface config the running ones.
+    fn load_conf(&self) -> Result<()> {
+        let conf = AwgInterfaceConf::parse(&self.read_file(&self.server.wg_conf())?, String::new())?
+            .ok_or(anyhow::anyhow!("wg syncconf failed: no [Interface]"))?;
+        *self.live.lock().unwrap() = conf.peers.into_keys().collect();
+        Ok(())
+    }
+
+    pub fn set_peer(&self, public_key: &str) -> Result<()> {
+        self.run("set_peer")?;
+        self.live.lock().unwrap().insert(public_key.to_string());
+        Ok(())
+    }
+
+    pub fn remove_peer(&self, public_key: &str) -> Result<()> {
+        self.run("remove_peer")?;
+        self.live.lock().unwrap().remove(public_key);
+        Ok(())
+    }
+
+    pub fn live_peers(&self) -> HashSet<String> {
+        self.live.lock().unwrap().clone()
+    }
+
+    /// Takes the rules from the `iptables` lines of `script`.
+    pub fn load_rules(&self, script: &str) -> Result<()> {
+        self.run("load_rules")?;
+        *self.rules.lock().unwrap() = Some(script.lines().filter_map(|l| l.strip_prefix("iptables ")).filter(|r| r.starts_with("-A ")).map(str::to_string).collect());
+        Ok(())
+    }
+
+    /// The rules in the isolation chain. None if no script created it yet.
+    pub fn live_rules(&self) -> Option<Vec<String>> {
+        self.rules.lock().unwrap().clone()
+    }
+
+    /// Drops the isolation chain, as a container restart does.
+    pub fn flush_rules(&self) {
+        *self.rules.lock().unwrap() = None;
+    }
+
+    /// Records a handshake of peer `public_key` now.
+    pub fn handshake(&self, public_key: &str) {
+        self.handshakes.lock().unwrap().insert(public_key.to_string(), chrono::Utc::now().timestamp());
+    }
+
+    pub fn latest_handshakes(&self) -> HashMap<String, i64> {
+        self.handshakes.lock().unwrap().clone()
+    }
+}
@@ -1,16 +1,13 @@
 use anyhow::Result;
-use std::{collections::HashMap};
+use std::collections::HashMap;
 
-use crate::{interactions::{client_table::ClientTableRecord, shared::command_in_docker}};
+use crate::interactions::{backend::Backend, client_table::{get_client_table, ClientTableRecord}};
 
-pub async fn get_users() -> Result<Vec<ClientTableRecord>> {
-    let output = command_in_docker(&["cat", "/opt/amnezia/awg/clientsTable"]).await?;
-    let data = String::from_utf8_lossy(&output.stdout);
-    let clients: Vec<ClientTableRecord> = serde_json::from_str(&data)?;
-    Ok(clients)
+pub async fn get_users(backend: &Backend) -> Result<Vec<ClientTableRecord>> {
+    get_client_table(backend).await
 }
 
-pub async fn get_users_map() -> Result<HashMap<String, ClientTableRecord>> {
-    let users = get_users().await?;
+pub async fn get_users_map(backend: &Backend) -> Result<HashMap<String, ClientTableRecord>> {
+    let users = get_users(backend).await?;
     Ok(users.into_iter().map(|u| (u.client_id.clone(), u)).collect())
 }
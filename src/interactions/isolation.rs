@@ -0,0 +1,46 @@
+pub use simple_awg_api_types::{Isolation, Ruleset};
+
+use crate::{config::ServerConfig, interactions::subnet::Subnet};
+
+/// Chain of the container's `filter` table holding the rules. Traffic coming
+/// in from the interface jumps to it from `FORWARD`.
+pub const CHAIN: &str = "AWG-ISOLATION";
+
+/// Rules restricting the traffic from each group's sources, its peers'
+/// addresses and the networks behind its sites, to what its isolation allows.
+/// `private` are the VPN subnet and the networks behind sites, the
+/// destinations that aren't "the internet". The rules are written the way
+/// `iptables -S` lists them, so they can be compared with the running chain.
+pub fn rules(groups: &[(&Isolation, Vec<Subnet>)], private: &[Subnet]) -> Vec<String> {
+    if groups.iter().all(|(_, sources)| sources.is_empty()) {
+        return vec![];
+    }
+    let mut rules = vec![format!("-A {CHAIN} -m conntrack --ctstate RELATED,ESTABLISHED -j ACCEPT")];
+    for (isolation, sources) in groups {
+        let allow: Vec<Subnet> = isolation.allow.iter().filter_map(|a| a.parse().ok()).collect();
+        for source in sources {
+            for allowed in allow.iter() {
+                rules.push(format!("-A {CHAIN} -s {source} -d {allowed} -j ACCEPT"));
+            }
+            if isolation.internet {
+                rules.extend(private.iter().map(|subnet| format!("-A {CHAIN} -s {source} -d {subnet} -j DROP")));
+            } else {
+                rules.push(format!("-A {CHAIN} -s {source} -j DROP"));
+            }
+        }
+    }
+    rules
+}
+
+/// Script that replaces the chain's rules with `rules` and hooks it up once.
+pub fn script(server: &ServerConfig, rules: &[String]) -> String {
+    let iface = &server.interface;
+    let mut lines = vec![
+        "set -e".to_string(),
+        format!("iptables -N {CHAIN} 2>/dev/null || true"),
+        format!("iptables -F {CHAIN}"),
+        format!("iptables -C FORWARD -i {iface} -j {CHAIN} 2>/dev/null || iptables -I FORWARD 1 -i {iface} -j {CHAIN}"),
+    ];
+    lines.extend(rules.iter().map(|r| format!("iptables {r}")));
+    lines.join("\n") + "\n"
+}
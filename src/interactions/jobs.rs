@@ -0,0 +1,123 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use chrono::{DateTime, Local};
+use tokio::{sync::RwLock, task::JoinSet, time::Instant};
+use tracing::info;
+use uuid::Uuid;
+
+use crate::interactions::batch::{Batcher, OpOutcome};
+
+pub use simple_awg_api_types::{CreateRequest, ItemStatus, Job, JobItem, JobRef, JobStatus};
+
+/// How long a finished job stays queryable.
+const JOB_RETENTION: Duration = Duration::from_secs(60 * 60);
+
+/// In-memory registry of batch jobs, pruned of finished jobs after `JOB_RETENTION`.
+#[derive(Clone, Default)]
+pub struct Jobs {
+    jobs: Arc<RwLock<HashMap<String, Entry>>>,
+}
+
+struct Entry {
+    job: Job,
+    finished_at: Option<Instant>,
+}
+
+fn now() -> String {
+    let now: DateTime<Local> = Local::now();
+    now.to_rfc3339()
+}
+
+impl Jobs {
+    pub async fn get(&self, id: &str) -> Option<Job> {
+        self.prune().await;
+        self.jobs.read().await.get(id).map(|e| e.job.clone())
+    }
+
+    async fn prune(&self) {
+        self.jobs.write().await.retain(|_, e| e.finished_at.is_none_or(|t| t.elapsed() < JOB_RETENTION));
+    }
+
+    /// Registers a job for `batch` and creates the users in the background
+    /// through `batcher`. Returns the job id right away.
+    pub async fn start_create(&self, batcher: Batcher, batch: Vec<CreateRequest>) -> JobRef {
+        self.prune().await;
+        let id = Uuid::new_v4().simple().to_string();
+        let job = Job {
+            id: id.clone(),
+            status: JobStatus::Running,
+            total: batch.len(),
+            done: 0,
+            failed: 0,
+            created: now(),
+            finished: None,
+            items: batch.iter().map(|r| JobItem {
+                name: r.name.clone(),
+                group: r.group.clone(),
+                status: ItemStatus::Pending,
+                id: None,
+                record: None,
+                error: None,
+            }).collect(),
+            records: vec![],
+        };
+        self.jobs.write().await.insert(id.clone(), Entry { job, finished_at: None });
+
+        let jobs = self.clone();
+        let job_id = id.clone();
+        tokio::spawn(async move {
+            let mut set = JoinSet::new();
+            let mut items = HashMap::new();
+            for (i, req) in batch.into_iter().enumerate() {
+                let batcher = batcher.clone();
+                let task = set.spawn(async move { (i, batcher.create(req).await) });
+                items.insert(task.id(), i);
+            }
+            while let Some(joined) = set.join_next().await {
+                match joined {
+                    Ok((i, r)) => jobs.finish_item(&job_id, i, r).await,
+                    // A panicked task still counts as a failed item.
+                    Err(e) => {
+                        if let Some(i) = items.get(&e.id()) {
+                            jobs.finish_item(&job_id, *i, Err(anyhow::anyhow!("Task failed: {}", e))).await;
+                        }
+                    }
+                }
+            }
+            jobs.finish(&job_id).await;
+        });
+        JobRef { id }
+    }
+
+    async fn finish_item(&self, id: &str, i: usize, r: anyhow::Result<OpOutcome>) {
+        let mut jobs = self.jobs.write().await;
+        let Some(Entry { job, .. }) = jobs.get_mut(id) else {return};
+        let Some(item) = job.items.get_mut(i) else {return};
+        match r {
+            Ok(OpOutcome::Created { id, record, .. }) => {
+                item.status = ItemStatus::Ok;
+                item.id = Some(id);
+                item.record = Some(record.clone());
+                if !job.records.contains(&record) {
+                    job.records.push(record);
+                }
+            }
+            Ok(_) => item.status = ItemStatus::Ok,
+            Err(e) => {
+                item.status = ItemStatus::Failed;
+                item.error = Some(format!("{:#}", e));
+                job.failed += 1;
+            }
+        }
+        job.done += 1;
+    }
+
+    async fn finish(&self, id: &str) {
+        let mut jobs = self.jobs.write().await;
+        let Some(Entry { job, finished_at }) = jobs.get_mut(id) else {return};
+        job.status = if job.failed == 0 { JobStatus::Completed } else { JobStatus::CompletedWithErrors };
+        job.finished = Some(now());
+        *finished_at = Some(Instant::now());
+        info!("Job {} finished: {}/{} failed", id, job.failed, job.total);
+    }
+}
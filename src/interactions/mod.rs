@@ -4,3 +4,17 @@ pub mod client_table;
 pub mod shared;
 pub mod get;
 pub mod cfg;
+pub mod transaction;
+pub mod batch;
+pub mod jobs;
+pub mod error;
+pub mod validation;
+pub mod backend;
+#[cfg(any(test, feature = "fake"))]
+pub mod fake;
+pub mod templates;
+pub mod obfuscation;
+pub mod placement;
+pub mod isolation;
+pub mod subnet;
+pub mod schedule;
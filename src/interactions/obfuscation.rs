@@ -0,0 +1,97 @@
+use anyhow::Result;
+use rand::random_range;
+
+pub use simple_awg_api_types::ObfuscationParams;
+
+use crate::interactions::{error::AwgError, validation::FieldError, wg0::AWGInterfaceData};
+
+// Limits from the AmneziaWG docs: junk packets and padded handshake messages
+// must still fit a 1280 byte MTU.
+const JC_MAX: u32 = 128;
+const JMAX_MAX: u32 = 1280;
+/// 1280 minus the 148 byte handshake initiation.
+const S1_MAX: u32 = 1132;
+/// 1280 minus the 92 byte handshake response.
+const S2_MAX: u32 = 1188;
+/// 1-4 are WireGuard's own message types.
+const H_MIN: u32 = 5;
+
+/// A fresh random parameter set within the ranges AmneziaWG recommends.
+pub fn generate() -> ObfuscationParams {
+    let jc = random_range(4..=12);
+    let jmin = random_range(8..=64);
+    let jmax = random_range(jmin + 16..=jmin + 256);
+    let s1 = random_range(15..=150);
+    let s2 = loop {
+        let s2 = random_range(15..=150);
+        if s1 + 56 != s2 {
+            break s2;
+        }
+    };
+    let mut h: Vec<u32> = vec![];
+    while h.len() < 4 {
+        let v = random_range(H_MIN..=i32::MAX as u32);
+        if !h.contains(&v) {
+            h.push(v);
+        }
+    }
+    let [h1, h2, h3, h4] = [h[0], h[1], h[2], h[3]].map(|v| v.to_string());
+    ObfuscationParams { jc, jmin, jmax, s1, s2, h1, h2, h3, h4 }
+}
+
+/// Bounds of an H value: `5` or a `100-200` range.
+pub fn header_range(h: &str) -> Option<(u32, u32)> {
+    let (lo, hi) = h.split_once('-').unwrap_or((h, h));
+    let (lo, hi) = (lo.trim().parse().ok()?, hi.trim().parse().ok()?);
+    (lo <= hi).then_some((lo, hi))
+}
+
+/// Every constraint `p` breaks.
+pub fn check(p: &ObfuscationParams) -> Vec<FieldError> {
+    let mut errors = vec![];
+    if !(1..=JC_MAX).contains(&p.jc) {
+        errors.push(FieldError::new("jc", format!("must be between 1 and {}", JC_MAX)));
+    }
+    if p.jmin >= p.jmax || p.jmax > JMAX_MAX {
+        errors.push(FieldError::new("jmax", format!("must be above jmin and at most {}", JMAX_MAX)));
+    }
+    if p.s1 > S1_MAX {
+        errors.push(FieldError::new("s1", format!("must be at most {}", S1_MAX)));
+    }
+    if p.s2 > S2_MAX {
+        errors.push(FieldError::new("s2", format!("must be at most {}", S2_MAX)));
+    }
+    // Padded initiation and response would have the same size.
+    if p.s1 + 56 == p.s2 {
+        errors.push(FieldError::new("s2", "must not equal s1 + 56"));
+    }
+    let mut seen: Vec<(u32, u32)> = vec![];
+    for (i, h) in [&p.h1, &p.h2, &p.h3, &p.h4].into_iter().enumerate() {
+        let field = format!("h{}", i + 1);
+        match header_range(h) {
+            None => errors.push(FieldError::new(field, format!("{:?} is not a number or a low-high range", h))),
+            Some((lo, _)) if lo < H_MIN => errors.push(FieldError::new(field, format!("must be at least {}", H_MIN))),
+            Some((lo, hi)) if seen.iter().any(|(l, h)| lo <= *h && *l <= hi) => {
+                errors.push(FieldError::new(field, "must not overlap the other headers"));
+            }
+            Some(range) => seen.push(range),
+        }
+    }
+    errors
+}
+
+/// The parameters set in the interface config.
+pub fn of(iface: &AWGInterfaceData) -> Result<ObfuscationParams> {
+    let n = |field: &str, v: &str| v.parse::<u32>().map_err(|_| AwgError::ConfigParse(format!("{} of wg0.conf: {:?}", field, v)));
+    Ok(ObfuscationParams {
+        jc: n("Jc", &iface.jc)?,
+        jmin: n("Jmin", &iface.jmin)?,
+        jmax: n("Jmax", &iface.jmax)?,
+        s1: n("S1", &iface.s1)?,
+        s2: n("S2", &iface.s2)?,
+        h1: iface.h1.clone(),
+        h2: iface.h2.clone(),
+        h3: iface.h3.clone(),
+        h4: iface.h4.clone(),
+    })
+}
@@ -1,40 +1,100 @@
-use std::collections::HashMap;
 use serde::Serialize;
 
+use crate::{config::config, interactions::{templates, validation::conf_file_name}};
+
 #[derive(Serialize)]
 pub struct Config {
     name: String,
     file: String,
-    config: String
+    config: String,
+    /// Configs with the same `user` belong to one user, one per location.
+    user: String,
+    location: String,
+    /// Device name, if the user has several devices.
+    device: Option<String>,
+    /// Tells the user's configs apart: the device name, else the location.
+    label: String,
+}
+
+/// A config to show on a page.
+pub struct Entry<'a> {
+    /// Id of the user the config belongs to.
+    pub user: &'a str,
+    pub name: &'a str,
+    pub location: String,
+    pub device: Option<&'a str>,
+    pub config: &'a str,
 }
 #[derive(Serialize)]
 pub struct PageData {
-    configs: Vec<Config>
+    /// `configs` as JSON, safe to place inside a `<script>` element.
+    configs_json: String
+}
+
+/// Serialises `value` for embedding in an inline script: `<`, `>` and `&` are
+/// escaped so names or configs can't close the script tag or open a comment.
+fn script_json<T: Serialize>(value: &T) -> anyhow::Result<String> {
+    Ok(serde_json::to_string(value)?
+        .replace('<', "\\u003c")
+        .replace('>', "\\u003e")
+        .replace('&', "\\u0026"))
 }
 
-pub async fn set_page(guid: &str, data: &HashMap<String, (String, String)>) {
-    if data.is_empty() {
+pub async fn set_page(guid: &str, entries: Vec<Entry<'_>>) -> anyhow::Result<()> {
+    if entries.is_empty() {
         remove_page(guid).await.ok();
-        return;
+        return Ok(());
     }
+    let contents = render(entries)?;
+    let dir = format!("{}/{guid}", config().served_dir);
+    tokio::fs::create_dir_all(&dir).await.ok();
+    tokio::fs::write(format!("{dir}/index.html"), contents).await?;
+    Ok(())
+}
+
+/// The share page listing `entries`.
+fn render(mut entries: Vec<Entry<'_>>) -> anyhow::Result<String> {
+    entries.sort_by(|a, b| (a.name, a.user, a.device.is_none(), a.device, &a.location).cmp(&(b.name, b.user, b.device.is_none(), b.device, &b.location)));
     let mut configs = vec![];
-    for (_id, (n, c)) in data.iter(){
+    for e in entries.iter() {
+        let label = e.device.unwrap_or(&e.location);
+        // Users with several configs get one file per device or location.
+        let file = if entries.iter().filter(|o| o.user == e.user).count() > 1 {
+            conf_file_name(&format!("{}-{}", e.name, label))
+        } else {
+            conf_file_name(e.name)
+        };
         configs.push(Config{
-            name: n.clone(),
-            file: format!("{n}.conf"),
-            config: c.clone()
+            name: e.name.to_string(),
+            file,
+            config: e.config.to_string(),
+            user: e.user.to_string(),
+            location: e.location.clone(),
+            device: e.device.map(str::to_string),
+            label: label.to_string(),
         });
     }
-    let mut h = handlebars::Handlebars::new();
-    h.register_template_file("index", "data/templates/index.hbs").expect("Failed to register index template");
-    let contents = h.render("index", &PageData{configs}).unwrap();
-    let dir = format!("data/served/{guid}");
-    tokio::fs::create_dir_all(&dir).await.ok();
-    tokio::fs::write(format!("{dir}/index.html"), contents).await.expect("Failed to write index.html");
+    templates::render(templates::INDEX, &PageData{configs_json: script_json(&configs)?})
 }
 
 pub async fn remove_page(guid: &str) -> anyhow::Result<()> {
-    let dir = format!("data/served/{guid}");
+    let dir = format!("{}/{guid}", config().served_dir);
     tokio::fs::remove_dir_all(dir).await.ok();
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_cannot_close_the_script() {
+        let name = "</script><script>alert(1)</script>";
+        let entry = Entry { user: "id", name, location: "default".to_string(), device: None, config: "[Interface]" };
+        let page = render(vec![entry]).unwrap();
+        assert!(!page.contains("</script><script>alert"));
+        let json = script_json(&name).unwrap();
+        assert!(!json.contains(['<', '>', '&']));
+        assert_eq!(serde_json::from_str::<String>(&json).unwrap(), name);
+    }
+}
@@ -0,0 +1,104 @@
+use std::{collections::{HashMap, VecDeque}, time::Duration};
+
+use tokio::time::Instant;
+
+pub use simple_awg_api_types::{Placement, PlacementPolicy, PlacementReason};
+
+/// How far back `LeastTraffic` looks.
+pub const TRAFFIC_WINDOW: Duration = Duration::from_secs(60 * 60);
+/// Minimum spacing of the samples kept within the window.
+pub const SAMPLE_EVERY: Duration = Duration::from_secs(5 * 60);
+
+/// What the placement policies weigh for one server.
+#[derive(Debug, Clone, Default)]
+pub struct Load {
+    pub peers: usize,
+    /// Bytes received plus sent by the server's peers within `TRAFFIC_WINDOW`.
+    pub bytes: u64,
+    /// Addresses left in the server's subnet.
+    pub free: u32,
+}
+
+impl Load {
+    /// Counts a user just placed on the server, so a batch spreads out.
+    pub fn add_peer(&mut self) {
+        self.peers += 1;
+        self.free = self.free.saturating_sub(1);
+    }
+
+    fn describe(&self, policy: PlacementPolicy) -> String {
+        match policy {
+            PlacementPolicy::LeastPeers => format!("{} peers", self.peers),
+            PlacementPolicy::LeastTraffic => format!("{} recent bytes", self.bytes),
+            PlacementPolicy::FreeAddresses => format!("{} free addresses", self.free),
+        }
+    }
+}
+
+/// Transfer counters of one server's peers as they were read over time, to
+/// tell recent traffic from the lifetime totals `clientsTable` reports.
+#[derive(Debug, Default)]
+pub struct TrafficSamples(VecDeque<(Instant, HashMap<String, u64>)>);
+
+impl TrafficSamples {
+    /// Records the current bytes of each peer, by client id.
+    pub fn record(&mut self, counters: HashMap<String, u64>) {
+        let now = Instant::now();
+        // The newest sample older than the window stays as the baseline.
+        while self.0.get(1).is_some_and(|(at, _)| now.duration_since(*at) >= TRAFFIC_WINDOW) {
+            self.0.pop_front();
+        }
+        if self.0.back().is_none_or(|(at, _)| now.duration_since(*at) >= SAMPLE_EVERY) {
+            self.0.push_back((now, counters));
+        }
+    }
+
+    /// Bytes `counters` grew by since the baseline. Peers that are newer, or
+    /// whose counters were reset since, count in full.
+    pub fn recent(&self, counters: &HashMap<String, u64>) -> u64 {
+        let baseline = self.0.front().map(|(_, c)| c);
+        counters.iter().map(|(id, bytes)| match baseline.and_then(|b| b.get(id)) {
+            Some(old) if old <= bytes => bytes - old,
+            _ => *bytes,
+        }).sum()
+    }
+}
+
+/// Index of the server `policy` picks out of `loads`, skipping full subnets;
+/// ties go to the first. Returns the decision along with it.
+pub fn pick(policy: PlacementPolicy, loads: &[(String, Load)]) -> Option<(usize, Placement)> {
+    let candidates = loads.iter().enumerate().filter(|(_, (_, l))| l.free > 0);
+    let (i, _) = match policy {
+        PlacementPolicy::LeastPeers => candidates.min_by_key(|(_, (_, l))| l.peers),
+        PlacementPolicy::LeastTraffic => candidates.min_by_key(|(_, (_, l))| l.bytes),
+        // `max_by_key` keeps the last of equal elements.
+        PlacementPolicy::FreeAddresses => candidates.rev().max_by_key(|(_, (_, l))| l.free),
+    }?;
+    let detail = loads.iter().map(|(name, l)| format!("{}: {}", name, l.describe(policy))).collect::<Vec<_>>().join(", ");
+    Some((i, Placement { reason: PlacementReason::Policy, policy: Some(policy), detail: Some(detail) }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn counters(peers: &[(&str, u64)]) -> HashMap<String, u64> {
+        peers.iter().map(|(id, bytes)| (id.to_string(), *bytes)).collect()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn old_traffic_ages_out() {
+        let mut samples = TrafficSamples::default();
+        samples.record(counters(&[("a", 0)]));
+        tokio::time::advance(SAMPLE_EVERY).await;
+        samples.record(counters(&[("a", 1_000_000)]));
+        assert_eq!(samples.recent(&counters(&[("a", 1_000_000)])), 1_000_000);
+
+        // Busy long ago, quiet since.
+        tokio::time::advance(TRAFFIC_WINDOW * 2).await;
+        samples.record(counters(&[("a", 1_000_000)]));
+        assert_eq!(samples.recent(&counters(&[("a", 1_000_500)])), 500);
+        // New peers and reset counters count in full.
+        assert_eq!(samples.recent(&counters(&[("a", 700), ("b", 300)])), 1_000);
+    }
+}
@@ -0,0 +1,58 @@
+use std::time::Duration;
+
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+
+use crate::{config::config, interactions::{placement::SAMPLE_EVERY, shared::AppState}};
+
+/// How often the isolation rules are checked against the running firewall.
+const ISOLATION_CHECK: Duration = Duration::from_secs(60);
+
+/// Rotates every server's obfuscation parameters every `obfuscation_rotate_hours`. The
+/// setting is read again after each wait, so a reload can change or turn it off.
+pub fn rotate_on_schedule(state: AppState) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            let hours = config().obfuscation_rotate_hours;
+            if hours == 0 {
+                tokio::time::sleep(Duration::from_secs(60)).await;
+                continue;
+            }
+            tokio::time::sleep(Duration::from_secs(hours * 3600)).await;
+            if config().obfuscation_rotate_hours == 0 {
+                continue;
+            }
+            for server in state.server_infos().await {
+                match state.rotate_obfuscation(&server.name).await {
+                    Ok(r) => info!("Scheduled obfuscation rotation of {} done, {} of {} configs changed", server.name, r.regenerated.changed.len(), r.regenerated.total),
+                    Err(e) => error!("Scheduled obfuscation rotation of {} failed: {:#}", server.name, e),
+                }
+            }
+        }
+    })
+}
+
+/// Reloads the isolation rules of servers that lost them, e.g. to a container
+/// restart, every `ISOLATION_CHECK`.
+pub fn enforce_on_schedule(state: AppState) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(ISOLATION_CHECK).await;
+            state.enforce_isolation().await;
+        }
+    })
+}
+
+/// Reads every server's `clientsTable` again every `SAMPLE_EVERY`, recording
+/// its traffic counters, so `least_traffic` placement sees the last hour even
+/// when nothing is written for a while.
+pub fn sample_on_schedule(state: AppState) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(SAMPLE_EVERY).await;
+            if let Err(e) = state.fetch_users().await {
+                warn!("Failed to sample traffic: {:#}", e);
+            }
+        }
+    })
+}
@@ -1,43 +1,83 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{collections::{HashMap, HashSet}, process::Stdio, sync::Arc};
 
-use anyhow::Result;
-use chrono::{NaiveDate, NaiveDateTime};
+use anyhow::{Context, Result};
 use serde::{Serialize, Deserialize};
-use tokio::{process::Command, sync::RwLock};
+use tracing::{error, info, warn};
+use tokio::{io::AsyncWriteExt, process::Command, sync::RwLock};
 use uuid::Uuid;
+use chrono::{DateTime, Utc};
 
-use crate::{interactions::{cfg::{self, create_users, drop_all, rm_by_id}, client_table::ClientTableRecord, get::get_users_map, pages::set_page}, ENV};
-
-pub async fn sync_wg() -> Result<std::process::Output> {
-    command_in_docker(&["bash", "-c", "wg syncconf wg0 <(wg-quick strip /opt/amnezia/awg/wg0.conf)"]).await
-}
+pub use simple_awg_api_types::{AwgCompat, ConfigChange, CreateRequest, Device, GroupRecord, GroupSettings, ObfuscationRotation, PendingClient, RegenerateReport, ReloadReport, ServerInfo, ServerKeyRotation, ServerKeyStatus, SkippedConfig, User, UserLocation, UserResource, UserStats, UserUsage};
 
+use crate::{interactions::{batch::{Batcher, Link, OpOutcome, UserOp}, error::AwgError, validation::{check_key, check_label, check_site, FieldError, Site}, subnet::Subnet, isolation::{self, Isolation, Ruleset}, jobs::{JobRef, Jobs}, cfg::{self, drop_all, line_diff, rm_by_id, ClientConfig, ClientKeys, NewPeer}, client_table::ClientTableRecord, get::get_users_map, pages::{remove_page, set_page, Entry}, transaction::Transaction, backend::Backend, obfuscation::{self, ObfuscationParams}, placement::{self, Load, Placement, PlacementReason, TrafficSamples}, templates, wg0::AwgInterfaceConf}, config::{self, config, ServerConfig}};
 
-pub async fn write_to_docker(data: &str, dst: &str) -> Result<()> {
+pub async fn write_to_docker(container: &str, data: &str, dst: &str) -> Result<()> {
     let tmp_id = Uuid::new_v4().simple().to_string();
     tokio::fs::write(format!("/tmp/{}", tmp_id), data).await?;
-    copy_to_docker(&format!("/tmp/{}", tmp_id), dst).await?;
+    let status = copy_to_docker(container, &format!("/tmp/{}", tmp_id), dst).await;
     shred(&format!("/tmp/{}", tmp_id)).await?;
+    if !status?.success() {
+        return Err(AwgError::ContainerUnreachable(format!("failed to copy {} to container", dst)).into());
+    }
     Ok(())
 }
 
-pub async fn command_in_docker(args: &[&str]) -> Result<std::process::Output> {
+pub async fn read_from_docker(container: &str, src: &str) -> Result<String> {
+    let output = command_in_docker(container, &["cat", src]).await?;
+    if !output.status.success() {
+        return Err(anyhow::anyhow!("Failed to read {}: {}", src, String::from_utf8_lossy(&output.stderr)));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Maps a failed `docker` invocation to `AwgError::ContainerUnreachable` when the
+/// failure is docker's own (daemon down, container missing or stopped).
+fn check_docker(output: std::process::Output) -> Result<std::process::Output> {
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !output.status.success()
+        && (stderr.contains("Error response from daemon") || stderr.contains("Cannot connect to the Docker daemon")) {
+        return Err(AwgError::ContainerUnreachable(stderr.trim().to_string()).into());
+    }
+    Ok(output)
+}
+
+fn spawn_error(e: std::io::Error) -> anyhow::Error {
+    AwgError::ContainerUnreachable(format!("failed to run docker: {}", e)).into()
+}
+
+pub async fn command_in_docker(container: &str, args: &[&str]) -> Result<std::process::Output> {
+    let mut cmd = Command::new("docker");
+    cmd.args(["exec", "-i", container]);
+    for arg in args {
+        cmd.arg(arg);
+    };
+    check_docker(cmd.output().await.map_err(spawn_error)?)
+}
+
+/// Same as `command_in_docker`, but feeds `input` to the command's stdin.
+/// Used to hand secrets to `wg` without putting them on a command line.
+pub async fn command_in_docker_with_input(container: &str, args: &[&str], input: &str) -> Result<std::process::Output> {
     let mut cmd = Command::new("docker");
-    cmd.args(["exec", "-i", &ENV.container]);
+    cmd.args(["exec", "-i", container]);
     for arg in args {
         cmd.arg(arg);
     };
-    Ok(cmd.output().await?)
+    cmd.stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped());
+    let mut child = cmd.spawn().map_err(spawn_error)?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(input.as_bytes()).await?;
+    }
+    check_docker(child.wait_with_output().await?)
 }
 
-pub async fn copy_to_docker(src: &str, dst: &str) -> Result<std::process::ExitStatus> {
+pub async fn copy_to_docker(container: &str, src: &str, dst: &str) -> Result<std::process::ExitStatus> {
     let mut cmd = Command::new("docker");
     cmd.args([
             "cp",
             src,
-            &format!("{}:{}", ENV.container, dst),
+            &format!("{}:{}", container, dst),
         ]);
-    Ok(cmd.status().await?)
+    cmd.status().await.map_err(spawn_error)
 }
 
 pub async fn shred(src: &str) -> Result<std::process::ExitStatus> {
@@ -46,137 +86,1072 @@ pub async fn shred(src: &str) -> Result<std::process::ExitStatus> {
     Ok(cmd.status().await?)
 }
 
-#[derive(Clone, Default)]
+/// Device name of a user's own peer.
+const MAIN_DEVICE: &str = "main";
+
+/// Settings and parsed interface config of servers, by name.
+type Interfaces = HashMap<String, (ServerConfig, AwgInterfaceConf)>;
+
+/// An operation with the placement decision of a create.
+type PlacedOp<'a> = (&'a UserOp, Option<Placement>);
+
+#[derive(Clone)]
 pub struct AppState {
     pub stored: Arc<RwLock<StoredUsers>>,
+    pub jobs: Jobs,
+    /// Every server, by name. The first one is the default.
+    pub servers: Arc<Vec<Backend>>,
+    stored_file: Arc<str>,
+    batcher: Batcher,
+    /// Peer transfer counters of each server, sampled whenever users are fetched.
+    traffic: Arc<std::sync::Mutex<HashMap<String, TrafficSamples>>>,
 }
 
-#[derive(Serialize, Deserialize, Default)]
+#[derive(Serialize, Deserialize, Default, Clone)]
+#[serde(default)]
 pub struct StoredUsers {
     records: HashMap<String, ClientTableRecord>,
     pages: HashMap<String, HashMap<String, (String, String)>>,
     id_to_group: HashMap<String, String>,
     group_to_guid: HashMap<String, String>,
+    /// Address and keys of each config in `pages`, to rebuild it from.
+    keys: HashMap<String, ClientKeys>,
+    /// Groups without an entry use the defaults.
+    group_settings: HashMap<String, GroupSettings>,
+    /// Server of each peer. Peers without an entry are on the default server.
+    id_to_server: HashMap<String, String>,
+    /// How each peer's server was chosen.
+    id_to_placement: HashMap<String, Placement>,
+    /// Peers that are further locations of another user, mapped to that user's id.
+    location_of: HashMap<String, String>,
+    /// Peers that are further devices of another user, mapped to that user's id.
+    device_of: HashMap<String, String>,
+    /// Names of the peers in `device_of`.
+    device_names: HashMap<String, String>,
+    /// Users whose device limit differs from the `device_limit` setting.
+    device_limits: HashMap<String, usize>,
+    /// Unix time of each server's last keypair rotation.
+    key_rotations: HashMap<String, i64>,
+    /// Peers that are sites, with the networks behind them.
+    sites: HashMap<String, Site>,
+    /// Peers commented out of their server's `wg0.conf`.
+    suspended: HashSet<String>,
+}
+
+/// `StoredUsers` as saved with bincode, before the switch to JSON.
+#[derive(Deserialize)]
+struct StoredUsersV2 {
+    records: HashMap<String, ClientTableRecord>,
+    pages: HashMap<String, HashMap<String, (String, String)>>,
+    id_to_group: HashMap<String, String>,
+    group_to_guid: HashMap<String, String>,
+    keys: HashMap<String, ClientKeys>,
+    group_settings: HashMap<String, AwgCompat>,
+}
+
+impl From<StoredUsersV2> for StoredUsers {
+    fn from(v: StoredUsersV2) -> Self {
+        let group_settings = v.group_settings.into_iter().map(|(g, compat)| (g, GroupSettings { compat, ..Default::default() })).collect();
+        Self { records: v.records, pages: v.pages, id_to_group: v.id_to_group, group_to_guid: v.group_to_guid, keys: v.keys, group_settings, ..Default::default() }
+    }
+}
+
+/// `StoredUsers` as saved before group settings were stored.
+#[derive(Deserialize)]
+struct StoredUsersV1 {
+    records: HashMap<String, ClientTableRecord>,
+    pages: HashMap<String, HashMap<String, (String, String)>>,
+    id_to_group: HashMap<String, String>,
+    group_to_guid: HashMap<String, String>,
+    keys: HashMap<String, ClientKeys>,
+}
+
+impl From<StoredUsersV1> for StoredUsersV2 {
+    fn from(v: StoredUsersV1) -> Self {
+        Self { records: v.records, pages: v.pages, id_to_group: v.id_to_group, group_to_guid: v.group_to_guid, keys: v.keys, group_settings: HashMap::new() }
+    }
+}
+
+/// `StoredUsers` as saved before keys were stored.
+#[derive(Deserialize)]
+struct LegacyStoredUsers {
+    records: HashMap<String, ClientTableRecord>,
+    pages: HashMap<String, HashMap<String, (String, String)>>,
+    id_to_group: HashMap<String, String>,
+    group_to_guid: HashMap<String, String>,
+}
+
+impl From<LegacyStoredUsers> for StoredUsersV1 {
+    fn from(l: LegacyStoredUsers) -> Self {
+        let keys = l.pages.values()
+            .flat_map(|configs| configs.iter())
+            .filter_map(|(id, (_, config))| Some((id.clone(), ClientKeys::parse(config)?)))
+            .collect();
+        Self { records: l.records, pages: l.pages, id_to_group: l.id_to_group, group_to_guid: l.group_to_guid, keys }
+    }
+}
+
+impl StoredUsers {
+    /// Reads the JSON store, or migrates one of the older bincode layouts.
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        if let Ok(users) = serde_json::from_slice(bytes) {
+            return Some(users);
+        }
+        let config = bincode::config::standard();
+        if let Ok((v2, _)) = bincode::serde::decode_from_slice::<StoredUsersV2, _>(bytes, config) {
+            info!("Migrated stored users to JSON");
+            return Some(v2.into());
+        }
+        if let Ok((v1, _)) = bincode::serde::decode_from_slice::<StoredUsersV1, _>(bytes, config) {
+            info!("Migrated stored users, added group settings");
+            return Some(StoredUsersV2::from(v1).into());
+        }
+        let (legacy, _): (LegacyStoredUsers, _) = bincode::serde::decode_from_slice(bytes, config).ok()?;
+        info!("Migrated stored users, recovered keys from their configs");
+        Some(StoredUsersV2::from(StoredUsersV1::from(legacy)).into())
+    }
+
+    fn compat(&self, group: &str) -> AwgCompat {
+        self.group_settings.get(group).map(|g| g.compat).unwrap_or_default()
+    }
+
+    fn guid_for(&mut self, group: &str) -> String {
+        self.group_to_guid.entry(group.to_string())
+            .or_insert_with(|| Uuid::new_v4().simple().to_string())
+            .clone()
+    }
+
+    pub fn name_taken(&self, group: &str, name: &str) -> bool {
+        self.pages.get(group).is_some_and(|configs| configs.values().any(|(n, _)| n == name))
+    }
+
+    /// Id of the user peer `client_id` belongs to: its own unless it is a
+    /// further location or device.
+    fn user_of<'a>(&'a self, client_id: &'a str) -> &'a str {
+        self.location_of.get(client_id).or(self.device_of.get(client_id)).map_or(client_id, String::as_str)
+    }
+
+    fn is_user(&self, client_id: &str) -> bool {
+        self.records.contains_key(client_id) && self.user_of(client_id) == client_id
+    }
+
+    /// Peers holding the further locations of user `client_id`, by id.
+    fn locations_of(&self, client_id: &str) -> Vec<String> {
+        Self::owned(&self.location_of, client_id)
+    }
+
+    /// Peers of the further devices of user `client_id`, by id.
+    fn devices_of(&self, client_id: &str) -> Vec<String> {
+        Self::owned(&self.device_of, client_id)
+    }
+
+    /// Every peer of user `client_id` besides its own.
+    fn peers_of(&self, client_id: &str) -> Vec<String> {
+        [self.devices_of(client_id), self.locations_of(client_id)].concat()
+    }
+
+    fn owned(links: &HashMap<String, String>, client_id: &str) -> Vec<String> {
+        let mut ids: Vec<String> = links.iter().filter(|(_, u)| *u == client_id).map(|(id, _)| id.clone()).collect();
+        ids.sort();
+        ids
+    }
+
+    fn device_name(&self, client_id: &str) -> &str {
+        self.device_names.get(client_id).map_or(MAIN_DEVICE, String::as_str)
+    }
+
+    fn device_limit(&self, client_id: &str) -> usize {
+        self.device_limits.get(client_id).copied().unwrap_or(config::config().device_limit)
+    }
+
+    /// Whether user `user` may get a device called `name`, next to the
+    /// `pending` ones about to be created.
+    fn check_device(&self, user: &str, name: &str, pending: &[&str]) -> std::result::Result<(), AwgError> {
+        let devices = self.devices_of(user);
+        let mut names: Vec<&str> = devices.iter().map(|id| self.device_name(id)).chain(pending.iter().copied()).collect();
+        names.push(MAIN_DEVICE);
+        if names.contains(&name) {
+            return Err(AwgError::Conflict(format!("User {} already has a device named {}", user, name)));
+        }
+        let limit = self.device_limit(user);
+        if limit > 0 && names.len() >= limit {
+            return Err(AwgError::Conflict(format!("User {} has reached its limit of {} devices", user, limit)));
+        }
+        Ok(())
+    }
+
+    /// Moves everything stored about peer `old` over to the id `new`, links to it included.
+    fn rekey(&mut self, old: &str, new: &str) {
+        fn mv<T>(map: &mut HashMap<String, T>, old: &str, new: &str) {
+            if let Some(v) = map.remove(old) {
+                map.insert(new.to_string(), v);
+            }
+        }
+        if let Some(mut record) = self.records.remove(old) {
+            record.client_id = new.to_string();
+            self.records.insert(new.to_string(), record);
+        }
+        if let Some(configs) = self.id_to_group.get(old).and_then(|g| self.pages.get_mut(g)) {
+            mv(configs, old, new);
+        }
+        mv(&mut self.id_to_group, old, new);
+        mv(&mut self.keys, old, new);
+        mv(&mut self.id_to_server, old, new);
+        mv(&mut self.id_to_placement, old, new);
+        mv(&mut self.location_of, old, new);
+        mv(&mut self.device_of, old, new);
+        mv(&mut self.device_names, old, new);
+        mv(&mut self.device_limits, old, new);
+        mv(&mut self.sites, old, new);
+        if self.suspended.remove(old) {
+            self.suspended.insert(new.to_string());
+        }
+        for user in self.location_of.values_mut().chain(self.device_of.values_mut()).filter(|u| *u == old) {
+            *user = new.to_string();
+        }
+    }
+
+    async fn render_page(&self, group: &str) -> Result<()> {
+        let Some(guid) = self.group_to_guid.get(group) else {return Ok(())};
+        match self.pages.get(group) {
+            Some(configs) => {
+                let entries = configs.iter().map(|(id, (name, config))| {
+                    let user = self.user_of(id);
+                    let has_devices = !self.location_of.contains_key(id) && !self.devices_of(user).is_empty();
+                    Entry {
+                        user,
+                        name,
+                        location: self.id_to_server.get(id).map(|s| config::config().location(s)).unwrap_or_default(),
+                        device: has_devices.then(|| self.device_name(id)),
+                        config,
+                    }
+                }).collect();
+                set_page(guid, entries).await
+            }
+            None => remove_page(guid).await,
+        }
+    }
+}
+
+impl Default for AppState {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl AppState {
     pub fn new() -> Self {
-        if let Ok(b) =std::fs::read(&ENV.stored_file) {
-            if let Ok((users, _)) = bincode::serde::decode_from_slice(&b, bincode::config::standard()) {
-                Self {stored: Arc::new(RwLock::new(users))}
-            } else {
-                Default::default()
-            }
-        } else {
-            Default::default()
+        let servers = config().servers().keys().map(|name| Backend::docker(name)).collect();
+        Self::with_backends(servers, &config().stored_file)
+    }
+
+    /// State driving a single `backend`, persisted to `stored_file`.
+    pub fn with_backend(backend: Backend, stored_file: &str) -> Self {
+        Self::with_backends(vec![backend], stored_file)
+    }
+
+    /// State driving `servers`, the first being the default, persisted to `stored_file`.
+    pub fn with_backends(servers: Vec<Backend>, stored_file: &str) -> Self {
+        assert!(!servers.is_empty(), "at least one server is needed");
+        let users = std::fs::read(stored_file).ok()
+            .and_then(|b| StoredUsers::decode(&b))
+            .unwrap_or_default();
+        let (batcher, queue) = Batcher::new();
+        let state = Self {stored: Arc::new(RwLock::new(users)), jobs: Jobs::default(), servers: Arc::new(servers), stored_file: stored_file.into(), batcher, traffic: Arc::default()};
+        queue.spawn(state.clone());
+        state
+    }
+
+    async fn backup(&self, u: &StoredUsers) {
+        let b = serde_json::to_vec(u).unwrap();
+        tokio::fs::write(&*self.stored_file, b).await.ok();
+    }
+
+    /// Server of users that name none and whose group has none: the one
+    /// called `default`, else the first by name.
+    pub fn default_server(&self) -> &Backend {
+        self.server(config::DEFAULT_SERVER).unwrap_or(&self.servers[0])
+    }
+
+    pub fn server(&self, name: &str) -> Result<&Backend> {
+        self.servers.iter().find(|b| b.name() == name)
+            .ok_or_else(|| AwgError::NotFound(format!("Server {}", name)).into())
+    }
+
+    /// The server `client_id` lives on.
+    fn server_of(&self, s: &StoredUsers, client_id: &str) -> &Backend {
+        s.id_to_server.get(client_id).and_then(|name| self.server(name).ok()).unwrap_or(self.default_server())
+    }
+
+    /// Interface and settings of every server, by name.
+    async fn interfaces(&self) -> Result<Interfaces> {
+        let mut out = HashMap::new();
+        for backend in self.servers.iter() {
+            let server = backend.server();
+            let wg = AwgInterfaceConf::fetch(backend).await?
+                .ok_or(AwgError::ConfigParse(server.wg_conf()))?;
+            out.insert(backend.name().to_string(), (server, wg));
         }
+        Ok(out)
     }
 
-    async fn backup(u: &StoredUsers) {
-        let b = bincode::serde::encode_to_vec(&u, bincode::config::standard()).unwrap();
-        tokio::fs::write(&ENV.stored_file, b).await.ok();
+    /// Runs `f` against a fresh transaction on `backend` and the stored users.
+    /// If `f` fails, the container files, the stored users and the group pages
+    /// are restored to what they were before the call.
+    async fn transact<T>(&self, backend: &Backend, s: &mut StoredUsers, f: impl AsyncFnOnce(&mut Transaction, &mut StoredUsers) -> Result<T>) -> Result<T> {
+        let snapshot = s.clone();
+        let mut tx = Transaction::begin(backend.clone()).await?;
+        let e = match f(&mut tx, s).await {
+            Ok(r) => return Ok(r),
+            Err(e) => e,
+        };
+        if let Err(re) = tx.rollback().await {
+            tracing::error!("Failed to roll back container files: {:?}", re);
+        }
+        let failed = std::mem::replace(s, snapshot);
+        for (group, guid) in failed.group_to_guid.iter() {
+            if !s.group_to_guid.contains_key(group) {
+                remove_page(guid).await.ok();
+            }
+        }
+        for group in s.group_to_guid.keys() {
+            if failed.pages.get(group) != s.pages.get(group)
+                && let Err(pe) = s.render_page(group).await {
+                tracing::error!("Failed to restore page for {}: {:?}", group, pe);
+            }
+        }
+        Err(e)
     }
 
+    /// Reads the users of every server's `clientsTable`.
     pub async fn fetch_users(&self) -> Result<()> {
-        let users = get_users_map().await?;
-        self.stored.write().await.records = users;
+        let mut records = HashMap::new();
+        let mut servers = HashMap::new();
+        for backend in self.servers.iter() {
+            let users = get_users_map(backend).await?;
+            self.traffic.lock().unwrap().entry(backend.name().to_string()).or_default().record(transfer(users.values()));
+            for (id, record) in users {
+                servers.insert(id.clone(), backend.name().to_string());
+                records.insert(id, record);
+            }
+        }
+        let mut s = self.stored.write().await;
+        s.records = records;
+        s.id_to_server = servers;
         Ok(())
     }
-    
+
+    /// Deletes a peer, and with a user's own peer its further devices and locations too.
+    /// The locations go first; the user and its devices, all on the user's server,
+    /// then go in one transaction, so a failure never leaves devices without their user.
     pub async fn rm_by_id(&self, client_id: &str) -> Result<()> {
-        let mut s = self.stored.write().await;
-        rm_by_id(client_id).await?;
-        tracing::info!("Waiting for lock: {}", client_id);
-        tracing::info!("Got lock: {}", client_id);
-        s.records.remove(client_id);
-        if let Some(group) = s.id_to_group.remove(client_id) {
-            s.pages.remove(&group);
-            if let Some(guid) = s.group_to_guid.remove(&group) {
-                if let Some(configs) = s.pages.get_mut(&group) {
-                    configs.remove(client_id);
-                    set_page(&guid, configs).await;
-                };
-            }
+        let (devices, locations) = {
+            let s = self.stored.read().await;
+            (s.devices_of(client_id), s.locations_of(client_id))
+        };
+        let deletes = |ids: Vec<String>| ids.into_iter().map(|client_id| UserOp::Delete { client_id }).collect();
+        for r in self.batcher.submit_all(deletes(locations)).await? {
+            r?;
+        }
+        for r in self.batcher.submit_all(deletes([vec![client_id.to_string()], devices].concat())).await? {
+            r?;
         }
-        Self::backup(&*s).await;
-        drop(s);
-        self.fetch_users().await.ok();
         Ok(())
     }
 
-    pub async fn add_user(&self, name: &str, group: String) -> Result<GroupRecord> {
-        let mut s = self.stored.write().await;
-        let r = Self::add_user_raw(&mut s, name, group).await?;
-        Self::backup(&*s).await;
-        drop(s);
-        self.fetch_users().await.ok();
-        Ok(r)
+    /// Takes user `client_id` and its further devices and locations off their
+    /// interfaces without deleting them; their addresses stay taken.
+    pub async fn suspend_user(&self, client_id: &str) -> Result<()> {
+        self.set_suspended(client_id, true).await
     }
 
-    async fn add_user_raw(s: &mut StoredUsers, name: &str, group: String) -> Result<GroupRecord> {
-        let (public_id, config) = cfg::create_user(name).await?;
-        s.pages.entry(group.to_string()).or_default().insert(public_id.clone(), (name.to_string(), config));
-        tracing::info!("Pages: {:#?}", s.pages);
+    /// Puts a suspended user and its further devices and locations back.
+    pub async fn resume_user(&self, client_id: &str) -> Result<()> {
+        self.set_suspended(client_id, false).await
+    }
 
-        s.id_to_group.insert(public_id.clone(), group.to_string());
-        let guid = if let Some(k) = s.group_to_guid.get(&group) {
-            k.clone()
-        } else {
-            let guid = Uuid::new_v4().simple().to_string();
-            s.group_to_guid.insert(group.to_string(), guid.clone());
-            guid
+    async fn set_suspended(&self, client_id: &str, suspend: bool) -> Result<()> {
+        let peers = {
+            let s = self.stored.read().await;
+            if !s.is_user(client_id) {
+                return Err(AwgError::NotFound(format!("User {}", client_id)).into());
+            }
+            s.peers_of(client_id)
         };
+        let op = |client_id: String| if suspend { UserOp::Suspend { client_id } } else { UserOp::Resume { client_id } };
+        for r in self.batcher.submit_all([client_id.to_string()].into_iter().chain(peers).map(op).collect()).await? {
+            r?;
+        }
+        Ok(())
+    }
 
-        if let Some(configs) = s.pages.get(&group) {
-            set_page(&guid, configs).await;
+    /// Creates a user with its configs on every server in `req.locations` and
+    /// returns its client id with the group record.
+    pub async fn add_user(&self, req: CreateRequest) -> Result<(String, GroupRecord)> {
+        match self.batcher.create(req).await? {
+            OpOutcome::Created { id, record, .. } => Ok((id, record)),
+            _ => Err(anyhow::anyhow!("No user created")),
+        }
+    }
+
+    /// Gives user `client_id` a further config, on `server`.
+    pub async fn add_location(&self, client_id: &str, server: &str) -> Result<()> {
+        self.server(server)?;
+        let (name, group) = {
+            let s = self.stored.read().await;
+            let (Some(record), Some(group)) = (s.records.get(client_id), s.id_to_group.get(client_id)) else {
+                return Err(AwgError::NotFound(format!("User {}", client_id)).into());
+            };
+            if !s.is_user(client_id) {
+                return Err(AwgError::NotFound(format!("User {}", client_id)).into());
+            }
+            if s.suspended.contains(client_id) {
+                return Err(AwgError::Conflict(format!("User {} is suspended", client_id)).into());
+            }
+            let mut ids = s.locations_of(client_id);
+            ids.push(client_id.to_string());
+            if ids.iter().any(|id| self.server_of(&s, id).name() == server) {
+                return Err(AwgError::Conflict(format!("User {} already has a config on {}", client_id, server)).into());
+            }
+            (record.user_data.client_name.clone(), group.clone())
         };
-        Ok(GroupRecord{guid, group})
+        let op = UserOp::Create { name, group, server: Some(server.to_string()), link: Some(Link::Location(client_id.to_string())), public_key: None, address: None, site: None };
+        self.batcher.submit(op).await?;
+        Ok(())
     }
 
-    pub async fn add_users(&self, batch: Vec<(String, String)>) -> Result<Vec<GroupRecord>> {
-        let mut s = self.stored.write().await;
+    /// Gives user `client_id` a further device called `device`, on the user's
+    /// server. Returns the device's peer id.
+    pub async fn add_device(&self, client_id: &str, device: &str) -> Result<String> {
+        let (name, group, server) = {
+            let s = self.stored.read().await;
+            let (Some(record), Some(group)) = (s.records.get(client_id), s.id_to_group.get(client_id)) else {
+                return Err(AwgError::NotFound(format!("User {}", client_id)).into());
+            };
+            if !s.is_user(client_id) {
+                return Err(AwgError::NotFound(format!("User {}", client_id)).into());
+            }
+            if s.suspended.contains(client_id) {
+                return Err(AwgError::Conflict(format!("User {} is suspended", client_id)).into());
+            }
+            (record.user_data.client_name.clone(), group.clone(), self.server_of(&s, client_id).name().to_string())
+        };
+        let link = Link::Device { user: client_id.to_string(), name: device.to_string() };
+        match self.batcher.submit(UserOp::Create { name, group, server: Some(server), link: Some(link), public_key: None, address: None, site: None }).await? {
+            OpOutcome::Created { id, .. } => Ok(id),
+            _ => Err(anyhow::anyhow!("No device created")),
+        }
+    }
 
-        let mut names = Vec::with_capacity(batch.len());
-        let mut groups = Vec::with_capacity(batch.len());
-        for b in batch.into_iter() {
-            names.push(b.0);
-            groups.push(b.1);
+    /// Deletes device `device_id` of user `client_id`. The user's own peer
+    /// can't be removed this way; delete the user instead.
+    pub async fn remove_device(&self, client_id: &str, device_id: &str) -> Result<()> {
+        {
+            let s = self.stored.read().await;
+            if !s.is_user(client_id) {
+                return Err(AwgError::NotFound(format!("User {}", client_id)).into());
+            }
+            if device_id == client_id {
+                return Err(AwgError::Conflict("The user's own device can't be removed; delete the user instead".to_string()).into());
+            }
+            if s.device_of.get(device_id).is_none_or(|u| u != client_id) {
+                return Err(AwgError::NotFound(format!("Device {} of user {}", device_id, client_id)).into());
+            }
         }
-        let r = create_users(&names).await?;
-        let mut records = vec![];
-        for (i, (pid,  config)) in r.into_iter().enumerate() {
-            let Some(group) = groups.get(i) else {continue};
-            let Some(name) = names.get(i) else {continue};
+        self.batcher.submit(UserOp::Delete { client_id: device_id.to_string() }).await?;
+        Ok(())
+    }
 
-            s.id_to_group.insert(pid.clone(), group.to_string());
+    /// Gives user `client_id` a new keypair and PSK, keeping its address, name,
+    /// group, links and traffic counters. The client id is the public key, so
+    /// it changes too; returns the new one. A user that brought its own key
+    /// only gets a new PSK and keeps its id.
+    pub async fn rotate_keys(&self, client_id: &str) -> Result<String> {
+        let mut s = self.stored.write().await;
+        if !s.is_user(client_id) {
+            return Err(AwgError::NotFound(format!("User {}", client_id)).into());
+        }
+        if s.suspended.contains(client_id) {
+            return Err(AwgError::Conflict(format!("User {} is suspended; resume it first", client_id)).into());
+        }
+        let (Some(keys), Some(group)) = (s.keys.get(client_id).cloned(), s.id_to_group.get(client_id).cloned()) else {
+            return Err(AwgError::Conflict(format!("User {} has no stored config to rotate", client_id)).into());
+        };
+        let backend = self.server_of(&s, client_id);
+        let id = self.transact(backend, &mut s, async |tx, s| {
+            let (id, keys, config) = cfg::rotate_keys(tx, client_id, &keys, s.compat(&group), s.sites.get(client_id)).await?;
+            tx.apply().await?;
+            s.rekey(client_id, &id);
+            s.keys.insert(id.clone(), keys);
+            if let Some((_, page_config)) = s.pages.get_mut(&group).and_then(|c| c.get_mut(&id)) {
+                *page_config = config;
+            }
+            s.render_page(&group).await?;
+            Ok(id)
+        }).await?;
+        self.backup(&s).await;
+        Ok(id)
+    }
 
-            s.pages.entry(group.clone()).or_default().insert(pid.clone(), (name.clone(), config));
+    /// Traffic of user `client_id` summed over all of its peers.
+    pub async fn usage(&self, client_id: &str) -> Option<UserUsage> {
+        let s = self.stored.read().await;
+        let record = s.records.get(client_id).filter(|_| s.is_user(client_id))?;
+        let peers: Vec<&ClientTableRecord> = [client_id.to_string()].into_iter().chain(s.peers_of(client_id))
+            .filter_map(|id| s.records.get(&id))
+            .collect();
+        let (recv_bytes, sent_bytes) = peers.iter().map(|r| r.user_data.transfer()).fold((0, 0), |(r, t), (a, b)| (r + a, t + b));
+        Some(UserUsage {
+            id: client_id.to_string(),
+            name: record.user_data.client_name.clone(),
+            recv_bytes,
+            sent_bytes,
+            peers: peers.into_iter().map(UserStats::from).collect(),
+        })
+    }
 
-            let guid = if let Some(k) = s.group_to_guid.get(group) {
-                k.clone()
-            } else {
-                let guid = Uuid::new_v4().simple().to_string();
-                s.group_to_guid.insert(group.to_string(), guid.clone());
-                guid
-            };
+    /// Deletes user `client_id`'s config on `server`. The user's own server
+    /// can't be removed this way; delete the user instead.
+    pub async fn remove_location(&self, client_id: &str, server: &str) -> Result<()> {
+        let peer = {
+            let s = self.stored.read().await;
+            if !s.is_user(client_id) {
+                return Err(AwgError::NotFound(format!("User {}", client_id)).into());
+            }
+            if self.server_of(&s, client_id).name() == server {
+                return Err(AwgError::Conflict(format!("{} is the user's own server; delete the user instead", server)).into());
+            }
+            s.locations_of(client_id).into_iter().find(|id| self.server_of(&s, id).name() == server)
+                .ok_or_else(|| AwgError::NotFound(format!("Location {} of user {}", server, client_id)))?
+        };
+        self.batcher.submit(UserOp::Delete { client_id: peer }).await?;
+        Ok(())
+    }
 
-            if let Some(configs) = s.pages.get(group) {
-                set_page(&guid, configs).await;
+    /// Field errors for a batch of create requests: name rules, known servers, free
+    /// fixed addresses and, if `UNIQUE_NAMES` is set, uniqueness within the group
+    /// and within the batch.
+    /// Fields are reported as `name`/`group` for a single request and `[i].name` for batches.
+    pub async fn validate_create(&self, batch: &[CreateRequest]) -> Vec<FieldError> {
+        let s = self.stored.read().await;
+        let mut errors = vec![];
+        for (i, CreateRequest { name, group, server, locations, public_key, address, site }) in batch.iter().enumerate() {
+            let prefix = if batch.len() == 1 { String::new() } else { format!("[{}].", i) };
+            check_label(&format!("{prefix}name"), name, &mut errors);
+            check_label(&format!("{prefix}group"), group, &mut errors);
+            if config().unique_names && (s.name_taken(group, name) || batch[..i].iter().any(|r| r.name == *name && r.group == *group)) {
+                errors.push(FieldError::new(format!("{prefix}name"), format!("is already used in group {}", group)));
+            }
+            if let Some(server) = server
+                && self.server(server).is_err() {
+                errors.push(FieldError::new(format!("{prefix}server"), format!("unknown server {}", server)));
+            }
+            for location in locations.iter().filter(|l| self.server(l).is_err()) {
+                errors.push(FieldError::new(format!("{prefix}locations"), format!("unknown server {}", location)));
+            }
+            if let Some(key) = public_key {
+                check_key(&format!("{prefix}public_key"), key, &mut errors);
+                if !locations.is_empty() {
+                    errors.push(FieldError::new(format!("{prefix}public_key"), "can't be combined with locations"));
+                }
+                if s.records.contains_key(key) || batch[..i].iter().any(|r| r.public_key.as_ref() == Some(key)) {
+                    errors.push(FieldError::new(format!("{prefix}public_key"), "is already used by another peer"));
+                }
+            }
+            if let Some(site) = site {
+                check_site(&prefix, site, &mut errors);
+                if !locations.is_empty() {
+                    errors.push(FieldError::new(format!("{prefix}site"), "can't be combined with locations"));
+                }
+                let earlier = batch[..i].iter().filter_map(|r| r.site.as_ref());
+                let taken: Vec<Subnet> = self.servers.iter().filter_map(|b| Subnet::of_mask(&b.server().mask))
+                    .chain(s.sites.values().chain(earlier).flat_map(|s| s.routes.iter().filter_map(|r| r.parse().ok())))
+                    .collect();
+                for route in site.routes.iter().filter_map(|r| r.parse::<Subnet>().ok()) {
+                    if let Some(other) = taken.iter().find(|t| t.overlaps(&route)) {
+                        errors.push(FieldError::new(format!("{prefix}site.routes"), format!("{} overlaps {}", route, other)));
+                    }
+                }
+            }
+            if let Some(address) = address {
+                let field = format!("{prefix}address");
+                let pinned = server.as_ref().or(s.group_settings.get(group).and_then(|g| g.server.as_ref()));
+                let candidates: Vec<&Backend> = match pinned {
+                    Some(name) => self.server(name).into_iter().collect(),
+                    None => self.servers.iter().collect(),
+                };
+                match candidates.into_iter().find(|b| b.server().host_id(address).is_some()) {
+                    None => errors.push(FieldError::new(field, "is not a peer address in the subnet of the user's server")),
+                    Some(backend) => {
+                        let taken = s.keys.iter().any(|(id, k)| k.address == *address && self.server_of(&s, id).name() == backend.name());
+                        if taken || batch[..i].iter().any(|r| r.address.as_ref() == Some(address)) {
+                            errors.push(FieldError::new(field, "is already taken"));
+                        }
+                    }
+                }
+            }
+        }
+        errors
+    }
+
+    /// Starts creating `batch` in the background and returns the job tracking it.
+    pub async fn add_users(&self, batch: Vec<CreateRequest>) -> JobRef {
+        self.jobs.start_create(self.batcher.clone(), batch).await
+    }
+
+    /// Applies `ops` in one transaction per server. An operation fails on its
+    /// own, or with every other operation on its server if that transaction fails.
+    pub(crate) async fn run_ops(&self, ops: Vec<UserOp>) -> Vec<Result<OpOutcome>> {
+        let mut s = self.stored.write().await;
+        let mut results: Vec<Option<Result<OpOutcome>>> = ops.iter().map(|_| None).collect();
+        let mut by_server: Vec<(&Backend, Vec<usize>, Vec<PlacedOp>)> = vec![];
+        // Fetched on the first create left to a policy.
+        let mut loads: Option<Vec<(String, Load)>> = None;
+        for (i, op) in ops.iter().enumerate() {
+            let placed = match op {
+                UserOp::Create { group, server, address, .. } => {
+                    let settings = s.group_settings.get(group);
+                    let placed = match (server, settings.and_then(|g| g.server.as_ref()), address) {
+                        (Some(name), _, _) => self.server(name).map(|b| (b, Some(Placement { reason: PlacementReason::Requested, policy: None, detail: None }))),
+                        (None, Some(name), _) => self.server(name).map(|b| (b, Some(Placement { reason: PlacementReason::Pinned, policy: None, detail: None }))),
+                        (None, None, Some(address)) => self.servers.iter().find(|b| b.server().host_id(address).is_some())
+                            .map(|b| (b, Some(Placement { reason: PlacementReason::Requested, policy: None, detail: Some(format!("subnet of {}", address)) })))
+                            .ok_or_else(|| AwgError::Validation(vec![FieldError::new("address", "is not a peer address in the subnet of any server")]).into()),
+                        (None, None, None) => {
+                            let loads = match &mut loads {
+                                Some(loads) => loads,
+                                None => loads.insert(self.loads(&s).await),
+                            };
+                            let policy = settings.and_then(|g| g.placement).unwrap_or(config().placement);
+                            match placement::pick(policy, loads) {
+                                Some((j, p)) => self.server(&loads[j].0).map(|b| (b, Some(p))),
+                                None if loads.is_empty() => Err(anyhow::anyhow!("No server could be read to place the user on")),
+                                None => Err(AwgError::SubnetExhausted.into()),
+                            }
+                        }
+                    };
+                    if let (Ok((backend, _)), Some(loads)) = (&placed, &mut loads)
+                        && let Some((_, load)) = loads.iter_mut().find(|(name, _)| name == backend.name()) {
+                        load.add_peer();
+                    }
+                    placed
+                }
+                UserOp::Delete { client_id } | UserOp::Update { client_id, .. } | UserOp::Suspend { client_id } | UserOp::Resume { client_id } => {
+                    Ok((self.server_of(&s, client_id), None))
+                }
             };
-            records.push(GroupRecord{guid, group: group.to_string()});
+            match placed {
+                Ok((backend, placement)) => match by_server.iter_mut().find(|(b, _, _)| b.name() == backend.name()) {
+                    Some((_, indices, server_ops)) => {
+                        indices.push(i);
+                        server_ops.push((op, placement));
+                    }
+                    None => by_server.push((backend, vec![i], vec![(op, placement)])),
+                },
+                Err(e) => results[i] = Some(Err(e)),
+            }
+        }
+        for (backend, indices, server_ops) in by_server {
+            match self.apply_ops(backend, &mut s, &server_ops).await {
+                Ok(server_results) => {
+                    for (i, r) in indices.into_iter().zip(server_results) {
+                        results[i] = Some(r);
+                    }
+                }
+                Err(e) => {
+                    for i in indices {
+                        results[i] = Some(Err(AwgError::replicate(&e)));
+                    }
+                }
+            }
         }
 
-        Self::backup(&*s).await;
+        self.enforce(&s).await;
+        self.backup(&s).await;
         drop(s);
         self.fetch_users().await.ok();
-        Ok(records)
+        results.into_iter().map(|r| r.unwrap_or_else(|| Err(anyhow::anyhow!("Operation was not applied")))).collect()
     }
 
+    /// Current load of every server, for placement. Servers whose interface
+    /// config can't be read are left out.
+    async fn loads(&self, s: &StoredUsers) -> Vec<(String, Load)> {
+        let mut loads = vec![];
+        for backend in self.servers.iter() {
+            let wg = match AwgInterfaceConf::fetch(backend).await {
+                Ok(Some(wg)) => wg,
+                Ok(None) => {
+                    warn!("Leaving server {} out of placement: no interface config", backend.name());
+                    continue;
+                }
+                Err(e) => {
+                    warn!("Leaving server {} out of placement: {:#}", backend.name(), e);
+                    continue;
+                }
+            };
+            let records: Vec<&ClientTableRecord> = s.records.values().filter(|r| self.server_of(s, &r.client_id).name() == backend.name()).collect();
+            let counters = transfer(records.iter().copied());
+            let bytes = self.traffic.lock().unwrap().entry(backend.name().to_string()).or_default().recent(&counters);
+            let load = Load { peers: records.len(), bytes, free: wg.free_count(&backend.server().reserved_ids().into_iter().collect()) };
+            loads.push((backend.name().to_string(), load));
+        }
+        loads
+    }
 
+    /// Applies `ops` on `backend` in a single transaction, recording the
+    /// placement of each create. The outer error means nothing was applied;
+    /// the inner ones are per-operation failures that did not stop the rest.
+    async fn apply_ops(&self, backend: &Backend, s: &mut StoredUsers, ops: &[PlacedOp<'_>]) -> Result<Vec<Result<OpOutcome>>> {
+        self.transact(backend, s, async |tx, s| {
+            let mut results: Vec<Option<Result<OpOutcome>>> = ops.iter().map(|_| None).collect();
+            let mut peers: Vec<NewPeer> = vec![];
+            let mut creates = vec![];
+            let mut devices: Vec<(&String, &str)> = vec![];
+            let mut updates = vec![];
+            for (i, (op, placement)) in ops.iter().enumerate() {
+                match op {
+                    UserOp::Create { name, group, link, public_key, address, site, .. } => {
+                        if link.is_none() && config().unique_names
+                            && (s.name_taken(group, name) || creates.iter().any(|(_, n, g, _, _)| *n == name && *g == group)) {
+                            results[i] = Some(Err(AwgError::Conflict(format!("Name {} is already used in group {}", name, group)).into()));
+                            continue;
+                        }
+                        if let Some(key) = public_key
+                            && (tx.wg.has_peer(key) || peers.iter().any(|p| p.public_key.as_ref() == Some(key))) {
+                            results[i] = Some(Err(AwgError::Conflict(format!("Public key {} is already used by another peer", key)).into()));
+                            continue;
+                        }
+                        if let Some(address) = address {
+                            let server = tx.backend.server();
+                            let Some(id) = server.host_id(address) else {
+                                let error = FieldError::new("address", format!("is not in the subnet {}0/24 of server {}", server.mask, tx.backend.name()));
+                                results[i] = Some(Err(AwgError::Validation(vec![error]).into()));
+                                continue;
+                            };
+                            if tx.wg.used_ids().contains(&id) || peers.iter().any(|p| p.address.as_ref() == Some(address)) {
+                                results[i] = Some(Err(AwgError::Conflict(format!("Address {} is already taken", address)).into()));
+                                continue;
+                            }
+                        }
+                        if let Some(site) = site {
+                            let vpn = Subnet::of_mask(&tx.backend.server().mask);
+                            let taken: Vec<Subnet> = vpn.into_iter()
+                                .chain(tx.wg.all_peers().flat_map(|p| Subnet::list(&p.allowed_ips)))
+                                .chain(peers.iter().filter_map(|p| p.site.as_ref()).flat_map(|s| s.routes.iter().filter_map(|r| r.parse().ok())))
+                                .collect();
+                            let overlap = site.routes.iter().filter_map(|r| r.parse::<Subnet>().ok())
+                                .find_map(|r| taken.iter().find(|t| t.overlaps(&r)).map(|t| (r, *t)));
+                            if let Some((route, other)) = overlap {
+                                results[i] = Some(Err(AwgError::Conflict(format!("Route {} overlaps {}", route, other)).into()));
+                                continue;
+                            }
+                        }
+                        if let Some(Link::Device { user, name: device }) = link {
+                            let pending: Vec<&str> = devices.iter().filter(|(u, _)| *u == user).map(|(_, d)| *d).collect();
+                            if let Err(e) = s.check_device(user, device, &pending) {
+                                results[i] = Some(Err(e.into()));
+                                continue;
+                            }
+                            devices.push((user, device.as_str()));
+                        }
+                        peers.push(NewPeer { name: name.clone(), compat: s.compat(group), public_key: public_key.clone(), address: address.clone(), site: site.clone() });
+                        creates.push((i, name, group, placement, link));
+                    }
+                    UserOp::Delete { client_id } => {
+                        if tx.wg.has_peer(client_id) || s.id_to_group.contains_key(client_id) {
+                            rm_by_id(tx, client_id);
+                            results[i] = Some(Ok(OpOutcome::Deleted));
+                        } else {
+                            results[i] = Some(Err(AwgError::NotFound(format!("User {}", client_id)).into()));
+                        }
+                    }
+                    UserOp::Update { client_id, name, group, device_limit } => {
+                        match Self::plan_update(tx, s, client_id, name.as_deref(), group.as_deref(), *device_limit) {
+                            Ok(plan) => {
+                                updates.push((i, plan));
+                                results[i] = Some(Ok(OpOutcome::Updated));
+                            }
+                            Err(e) => results[i] = Some(Err(e.into())),
+                        }
+                    }
+                    UserOp::Suspend { client_id } | UserOp::Resume { client_id } => {
+                        if !tx.wg.has_peer(client_id) {
+                            results[i] = Some(Err(AwgError::NotFound(format!("User {}", client_id)).into()));
+                            continue;
+                        }
+                        // Suspending a suspended peer, or resuming an active one, changes nothing.
+                        if matches!(op, UserOp::Suspend { .. }) {
+                            tx.wg.suspend(client_id);
+                        } else {
+                            tx.wg.resume(client_id);
+                        }
+                        results[i] = Some(Ok(OpOutcome::Updated));
+                    }
+                }
+            }
+            let created = cfg::create_users(tx, &peers).await?;
+            tx.apply().await?;
 
+            for (i, (op, _)) in ops.iter().enumerate() {
+                if !matches!(results[i], Some(Ok(_))) {continue};
+                match op {
+                    UserOp::Suspend { client_id } => {
+                        s.suspended.insert(client_id.clone());
+                    }
+                    UserOp::Resume { client_id } => {
+                        s.suspended.remove(client_id);
+                    }
+                    _ => {}
+                }
+            }
+            let mut touched = vec![];
+            for (i, (op, _)) in ops.iter().enumerate() {
+                let UserOp::Delete { client_id } = op else {continue};
+                if !matches!(results[i], Some(Ok(_))) {continue};
+                s.records.remove(client_id);
+                s.keys.remove(client_id);
+                s.id_to_server.remove(client_id);
+                s.id_to_placement.remove(client_id);
+                s.location_of.remove(client_id);
+                s.device_of.remove(client_id);
+                s.device_names.remove(client_id);
+                s.device_limits.remove(client_id);
+                s.sites.remove(client_id);
+                s.suspended.remove(client_id);
+                if let Some(group) = s.id_to_group.remove(client_id) {
+                    if let Some(configs) = s.pages.get_mut(&group) {
+                        configs.remove(client_id);
+                    }
+                    touched.push(group);
+                }
+            }
+            for (_, plan) in updates {
+                if let Some(record) = s.records.get_mut(&plan.client_id) {
+                    record.user_data.client_name = plan.name.clone();
+                }
+                if let Some(limit) = plan.device_limit {
+                    s.device_limits.insert(plan.client_id.clone(), limit);
+                }
+                let Some(old_group) = plan.old_group else {continue};
+                let Some((_, mut config)) = s.pages.get_mut(&old_group).and_then(|c| c.remove(&plan.client_id)) else {continue};
+                let compat = s.compat(&plan.group);
+                if compat != s.compat(&old_group)
+                    && let Some(keys) = s.keys.get(&plan.client_id) {
+                    config = ClientConfig::from_keys(&tx.backend.server(), &tx.wg, keys, compat).for_site(s.sites.get(&plan.client_id)).render()?;
+                }
+                s.pages.entry(plan.group.clone()).or_default().insert(plan.client_id.clone(), (plan.name, config));
+                s.id_to_group.insert(plan.client_id, plan.group.clone());
+                s.guid_for(&plan.group);
+                touched.push(old_group);
+                touched.push(plan.group);
+            }
+            for ((pid, keys, config), (i, name, group, placement, link)) in created.into_iter().zip(creates) {
+                s.id_to_group.insert(pid.clone(), group.to_string());
+                s.id_to_server.insert(pid.clone(), tx.backend.name().to_string());
+                if let Some(placement) = placement {
+                    s.id_to_placement.insert(pid.clone(), placement.clone());
+                }
+                if let (UserOp::Create { site: Some(site), .. }, _) = &ops[i] {
+                    s.sites.insert(pid.clone(), site.clone());
+                }
+                match link {
+                    Some(Link::Location(user)) => {
+                        s.location_of.insert(pid.clone(), user.clone());
+                    }
+                    Some(Link::Device { user, name }) => {
+                        s.device_of.insert(pid.clone(), user.clone());
+                        s.device_names.insert(pid.clone(), name.clone());
+                    }
+                    None => {}
+                }
+                s.keys.insert(pid.clone(), keys);
+                s.pages.entry(group.clone()).or_default().insert(pid.clone(), (name.clone(), config));
+                let guid = s.guid_for(group);
+                results[i] = Some(Ok(OpOutcome::Created { id: pid, record: GroupRecord{guid, group: group.to_string(), config: None}, server: tx.backend.name().to_string() }));
+                touched.push(group.clone());
+            }
+            touched.sort();
+            touched.dedup();
+            for group in touched.iter() {
+                s.render_page(group).await?;
+            }
+            Ok(results.into_iter().map(|r| r.unwrap_or_else(|| Err(anyhow::anyhow!("Operation was not applied")))).collect())
+        }).await
+    }
+
+    /// Checks a rename/move and applies the rename to the transaction's `clientsTable`.
+    fn plan_update(tx: &mut Transaction, s: &StoredUsers, client_id: &str, name: Option<&str>, group: Option<&str>, device_limit: Option<usize>) -> std::result::Result<UpdatePlan, AwgError> {
+        let Some(record) = tx.clients_table.iter_mut().find(|c| c.client_id == client_id) else {
+            return Err(AwgError::NotFound(format!("User {}", client_id)));
+        };
+        if device_limit.is_some() && !s.is_user(client_id) {
+            return Err(AwgError::NotFound(format!("User {}", client_id)));
+        }
+        let old_group = s.id_to_group.get(client_id).cloned();
+        let new_name = name.unwrap_or(&record.user_data.client_name).to_string();
+        let new_group = match (group, &old_group) {
+            (Some(g), Some(_)) => g.to_string(),
+            (None, Some(g)) => g.clone(),
+            (Some(_), None) => return Err(AwgError::Conflict(format!("User {} has no stored config to move", client_id))),
+            (None, None) => String::new(),
+        };
+        if config().unique_names && old_group.is_some()
+            && s.pages.get(&new_group).is_some_and(|c| c.iter().any(|(id, (n, _))| s.user_of(id) != s.user_of(client_id) && *n == new_name)) {
+            return Err(AwgError::Conflict(format!("Name {} is already used in group {}", new_name, new_group)));
+        }
+        record.user_data.client_name = new_name.clone();
+        Ok(UpdatePlan { client_id: client_id.to_string(), name: new_name, old_group, group: new_group, device_limit })
+    }
 
     pub async fn group_records(&self) -> Vec<GroupRecord>{
-        self.stored.read().await.group_to_guid.clone().into_iter().map(|(group, guid)| GroupRecord{group, guid}).collect()
+        self.stored.read().await.group_to_guid.clone().into_iter().map(|(group, guid)| GroupRecord{group, guid, config: None}).collect()
+    }
+
+    /// The rendered config of peer `client_id`.
+    pub async fn client_config(&self, client_id: &str) -> Option<String> {
+        let s = self.stored.read().await;
+        let group = s.id_to_group.get(client_id)?;
+        s.pages.get(group)?.get(client_id).map(|(_, config)| config.clone())
+    }
+
+    pub async fn user(&self, client_id: &str) -> Option<UserResource> {
+        let s = self.stored.read().await;
+        s.records.get(client_id).filter(|_| s.is_user(client_id)).map(|r| self.resource(&s, r))
+    }
+
+    /// Users matching `group` and `server` (exact) and `name` (substring),
+    /// ordered by group, name and id.
+    pub async fn users(&self, group: Option<&str>, name: Option<&str>, server: Option<&str>) -> Vec<UserResource> {
+        let s = self.stored.read().await;
+        let mut users: Vec<UserResource> = s.records.values()
+            .filter(|r| s.is_user(&r.client_id))
+            .map(|r| self.resource(&s, r))
+            .filter(|u| group.is_none_or(|g| u.group.as_deref() == Some(g)))
+            .filter(|u| name.is_none_or(|n| u.name.contains(n)))
+            .filter(|u| server.is_none_or(|v| u.locations.iter().any(|l| l.server == v)))
+            .collect();
+        users.sort_by(|a, b| (&a.group, &a.name, &a.id).cmp(&(&b.group, &b.name, &b.id)));
+        users
+    }
+
+    /// Moves the group's share page to a fresh guid, so the old link stops working.
+    pub async fn rotate_link(&self, group: &str) -> Result<GroupRecord> {
+        let mut s = self.stored.write().await;
+        let Some(old) = s.group_to_guid.get(group).cloned() else {
+            return Err(AwgError::NotFound(format!("Group {}", group)).into());
+        };
+        let guid = Uuid::new_v4().simple().to_string();
+        s.group_to_guid.insert(group.to_string(), guid.clone());
+        if let Err(e) = s.render_page(group).await {
+            s.group_to_guid.insert(group.to_string(), old);
+            return Err(e);
+        }
+        remove_page(&old).await.ok();
+        self.backup(&s).await;
+        Ok(GroupRecord { group: group.to_string(), guid, config: None })
+    }
+
+    pub async fn group_settings(&self, group: &str) -> Result<GroupSettings> {
+        let s = self.stored.read().await;
+        if !s.group_to_guid.contains_key(group) {
+            return Err(AwgError::NotFound(format!("Group {}", group)).into());
+        }
+        Ok(s.group_settings.get(group).cloned().unwrap_or_default())
+    }
+
+    /// Stores the group's settings and rebuilds its configs to match them.
+    pub async fn set_group_settings(&self, group: &str, settings: GroupSettings) -> Result<RegenerateReport> {
+        let mut errors = vec![];
+        if let Some(server) = &settings.server
+            && self.server(server).is_err() {
+            errors.push(FieldError::new("server", format!("unknown server {}", server)));
+        }
+        if let Some(isolation) = &settings.isolation {
+            for e in isolation.allow.iter().filter_map(|a| a.parse::<Subnet>().err()) {
+                errors.push(FieldError::new("isolation.allow", e));
+            }
+        }
+        if !errors.is_empty() {
+            return Err(AwgError::Validation(errors).into());
+        }
+        let ifaces = self.interfaces().await?;
+        let mut s = self.stored.write().await;
+        if !s.group_to_guid.contains_key(group) {
+            return Err(AwgError::NotFound(format!("Group {}", group)).into());
+        }
+        let old = s.group_settings.insert(group.to_string(), settings);
+        match self.rebuild(&mut s, &ifaces, Some(group), false).await {
+            Ok(report) => {
+                self.enforce(&s).await;
+                Ok(report)
+            }
+            Err(e) => {
+                match old {
+                    Some(old) => s.group_settings.insert(group.to_string(), old),
+                    None => s.group_settings.remove(group),
+                };
+                Err(e)
+            }
+        }
+    }
+
+    fn resource(&self, s: &StoredUsers, record: &ClientTableRecord) -> UserResource {
+        let group = s.id_to_group.get(&record.client_id).cloned();
+        UserResource {
+            id: record.client_id.clone(),
+            name: record.user_data.client_name.clone(),
+            guid: group.as_ref().and_then(|g| s.group_to_guid.get(g).cloned()),
+            group,
+            server: Some(self.server_of(s, &record.client_id).name().to_string()),
+            placement: s.id_to_placement.get(&record.client_id).cloned(),
+            locations: [record.client_id.clone()].into_iter().chain(s.locations_of(&record.client_id)).map(|id| {
+                let server = self.server_of(s, &id).name().to_string();
+                UserLocation { location: config().location(&server), address: s.keys.get(&id).map(|k| k.address.clone()), server, id }
+            }).collect(),
+            devices: [record.client_id.clone()].into_iter().chain(s.devices_of(&record.client_id)).map(|id| Device {
+                name: s.device_name(&id).to_string(),
+                server: self.server_of(s, &id).name().to_string(),
+                address: s.keys.get(&id).map(|k| k.address.clone()),
+                created: s.records.get(&id).map(|r| r.user_data.creation_date.clone()).unwrap_or_default(),
+                id,
+            }).collect(),
+            device_limit: s.device_limit(&record.client_id),
+            site: s.sites.get(&record.client_id).cloned(),
+            own_key: s.keys.get(&record.client_id).is_some_and(|k| k.private_key.is_empty()),
+            suspended: s.suspended.contains(&record.client_id),
+            config: None,
+            created: record.user_data.creation_date.clone(),
+        }
+    }
+
+    pub async fn server_infos(&self) -> Vec<ServerInfo> {
+        let s = self.stored.read().await;
+        self.servers.iter().map(|b| {
+            let server = b.server();
+            ServerInfo {
+                name: b.name().to_string(),
+                location: config().location(b.name()),
+                host: server.host,
+                interface: server.interface,
+                users: s.records.keys().filter(|id| self.server_of(&s, id).name() == b.name()).count(),
+            }
+        }).collect()
+    }
+
+    pub async fn has_group(&self, group: &str) -> bool {
+        self.stored.read().await.group_to_guid.contains_key(group)
+    }
+
+    /// Renames and/or moves a user, along with its further devices and locations,
+    /// and sets how many devices it may have (0 lifts the limit). The limit is
+    /// applied together with the user's own rename or move, or not at all.
+    pub async fn update_user(&self, client_id: &str, name: Option<String>, group: Option<String>, device_limit: Option<usize>) -> Result<()> {
+        let peers = self.stored.read().await.peers_of(client_id);
+        self.batcher.submit(UserOp::Update { client_id: client_id.to_string(), name: name.clone(), group: group.clone(), device_limit }).await?;
+        for client_id in peers {
+            self.batcher.submit(UserOp::Update { client_id, name: name.clone(), group: group.clone(), device_limit: None }).await?;
+        }
+        Ok(())
     }
 
     pub async fn user_list(&self) -> Vec<User> {
@@ -188,46 +1163,282 @@ impl AppState {
         self.stored.read().await.records.iter().map(|c| c.1.into()).collect()
     }
 
-    pub async fn clear(&self) {
+    pub async fn group_stats(&self, group: &str) -> Vec<UserStats> {
+        let s = self.stored.read().await;
+        s.records.values().filter(|r| s.id_to_group.get(&r.client_id).is_some_and(|g| g == group)).map(|r| r.into()).collect()
+    }
+
+    /// Rebuilds every stored client config from its keys and address with the
+    /// current settings and server parameters, and rewrites the group pages.
+    /// With `dry_run` only reports the differences.
+    pub async fn regenerate(&self, dry_run: bool) -> Result<RegenerateReport> {
+        let ifaces = self.interfaces().await?;
         let mut s = self.stored.write().await;
-        drop_all().await.ok();
-        *s = StoredUsers::default();
-        tokio::fs::remove_dir_all("data/served").await.ok();
-        Self::backup(&*s).await;
+        self.rebuild(&mut s, &ifaces, None, dry_run).await
+    }
+
+    /// Renders every stored config on the servers in `ifaces` (and in group
+    /// `only`, if given) again and, unless `dry_run`, stores the ones that
+    /// changed and rewrites their pages.
+    async fn rebuild(&self, s: &mut StoredUsers, ifaces: &Interfaces, only: Option<&str>, dry_run: bool) -> Result<RegenerateReport> {
+        let mut report = RegenerateReport { dry_run, total: 0, changed: vec![], skipped: vec![] };
+        let mut rebuilt = vec![];
+        for (group, configs) in s.pages.iter().filter(|(g, _)| only.is_none_or(|o| o == *g)) {
+            for (id, (name, old)) in configs.iter() {
+                let Some((server, wg)) = ifaces.get(self.server_of(s, id).name()) else {continue};
+                report.total += 1;
+                let Some(keys) = s.keys.get(id).cloned().or_else(|| ClientKeys::parse(old)) else {
+                    report.skipped.push(SkippedConfig { id: id.clone(), name: name.clone(), group: group.clone(), reason: "no keys stored or found in the config".to_string() });
+                    continue;
+                };
+                let new = ClientConfig::from_keys(server, wg, &keys, s.compat(group)).for_site(s.sites.get(id)).render()?;
+                if new != *old {
+                    report.changed.push(ConfigChange { id: id.clone(), name: name.clone(), group: group.clone(), diff: line_diff(old, &new) });
+                }
+                rebuilt.push((group.clone(), id.clone(), keys, new));
+            }
+        }
+        report.changed.sort_by(|a, b| (&a.group, &a.name, &a.id).cmp(&(&b.group, &b.name, &b.id)));
+        if dry_run {
+            return Ok(report);
+        }
+
+        let snapshot = s.clone();
+        let mut touched = vec![];
+        for (group, id, keys, new) in rebuilt {
+            s.keys.insert(id.clone(), keys);
+            let Some((_, config)) = s.pages.get_mut(&group).and_then(|c| c.get_mut(&id)) else {continue};
+            if *config != new {
+                *config = new;
+                touched.push(group);
+            }
+        }
+        touched.sort();
+        touched.dedup();
+        for group in touched.iter() {
+            if let Err(e) = s.render_page(group).await {
+                *s = snapshot;
+                for group in touched.iter() {
+                    s.render_page(group).await.ok();
+                }
+                return Err(e);
+            }
+        }
+        self.backup(s).await;
+        info!("Regenerated {} of {} configs", report.changed.len(), report.total);
+        Ok(report)
+    }
+
+    /// The server's current obfuscation parameters.
+    pub async fn obfuscation(&self, server: &str) -> Result<ObfuscationParams> {
+        let backend = self.server(server)?;
+        let wg = AwgInterfaceConf::fetch(backend).await?
+            .ok_or(AwgError::ConfigParse(backend.server().wg_conf()))?;
+        obfuscation::of(&wg.parsed_iface)
+    }
+
+    /// Puts a fresh random obfuscation parameter set into the server's interface,
+    /// restarts it and rebuilds the server's client configs with the new values.
+    /// If the configs can't be rebuilt, the old parameters are restored.
+    pub async fn rotate_obfuscation(&self, server: &str) -> Result<ObfuscationRotation> {
+        let backend = self.server(server)?;
+        let params = obfuscation::generate();
+        let errors = obfuscation::check(&params);
+        if !errors.is_empty() {
+            return Err(AwgError::Validation(errors).into());
+        }
+        let mut s = self.stored.write().await;
+        let regenerated = self.transact(backend, &mut s, async |tx, s| {
+            tx.wg.set_obfuscation(&params)?;
+            tx.apply_restart().await?;
+            let ifaces = HashMap::from([(server.to_string(), (backend.server(), tx.wg.clone()))]);
+            self.rebuild(s, &ifaces, None, false).await
+                .context("Failed to regenerate client configs, obfuscation parameters rolled back")
+        }).await?;
+        info!("Rotated obfuscation parameters of {}, interface restarted", server);
+        Ok(ObfuscationRotation { params, regenerated })
+    }
+
+    /// Gives server `server` a new keypair, restarts its interface and regenerates
+    /// the server's client configs and the pages. If the configs can't be
+    /// regenerated, the old keypair is restored. The time is kept, so
+    /// [`server_key_status`](Self::server_key_status) can tell which clients
+    /// have not connected with their new config since.
+    pub async fn rotate_server_key(&self, server: &str) -> Result<ServerKeyRotation> {
+        let backend = self.server(server)?;
+        let (public_key, private_key, _) = backend.generate_keys().await?;
+        let mut s = self.stored.write().await;
+        let now = Utc::now();
+        let regenerated = self.transact(backend, &mut s, async |tx, s| {
+            tx.wg.set_keypair(&private_key, &public_key)?;
+            tx.apply_restart().await?;
+            s.key_rotations.insert(server.to_string(), now.timestamp());
+            let ifaces = HashMap::from([(server.to_string(), (backend.server(), tx.wg.clone()))]);
+            self.rebuild(s, &ifaces, None, false).await
+                .context("Failed to regenerate client configs, server keypair rolled back")
+        }).await?;
+        info!("Rotated keypair of {}, interface restarted", server);
+        Ok(ServerKeyRotation { server: server.to_string(), public_key, rotated_at: now.to_rfc3339(), regenerated })
+    }
+
+    /// The peers of server `server` without a handshake since its last keypair rotation.
+    pub async fn server_key_status(&self, server: &str) -> Result<ServerKeyStatus> {
+        let backend = self.server(server)?;
+        let public_key = backend.read_file(&backend.server().server_public_key()).await?.trim().to_string();
+        let handshakes = backend.latest_handshakes().await?;
+        let s = self.stored.read().await;
+        let rotated = s.key_rotations.get(server).copied();
+        let rfc3339 = |t: i64| DateTime::from_timestamp(t, 0).map(|t| t.to_rfc3339());
+        let peers: Vec<&ClientTableRecord> = s.records.values().filter(|r| self.server_of(&s, &r.client_id).name() == server).collect();
+        let mut pending: Vec<PendingClient> = peers.iter()
+            .filter(|r| rotated.is_some_and(|at| handshakes.get(&r.client_id).is_none_or(|t| *t < at)))
+            .map(|r| PendingClient {
+                id: r.client_id.clone(),
+                name: r.user_data.client_name.clone(),
+                group: s.id_to_group.get(&r.client_id).cloned(),
+                latest_handshake: handshakes.get(&r.client_id).and_then(|t| rfc3339(*t)),
+            })
+            .collect();
+        pending.sort_by(|a, b| a.name.cmp(&b.name).then_with(|| a.id.cmp(&b.id)));
+        Ok(ServerKeyStatus { server: server.to_string(), public_key, rotated_at: rotated.and_then(rfc3339), total: peers.len(), pending })
+    }
+
+    /// The isolation rules of server `server` for its current peers.
+    pub async fn ruleset(&self, server: &str) -> Result<Ruleset> {
+        let backend = self.server(server)?;
+        Ok(self.rules_of(&*self.stored.read().await, backend))
+    }
+
+    /// Loads the isolation rules on every server that lacks them, e.g. after
+    /// its container restarted.
+    pub async fn enforce_isolation(&self) {
+        self.enforce(&*self.stored.read().await).await;
+    }
+
+    fn rules_of(&self, s: &StoredUsers, backend: &Backend) -> Ruleset {
+        let server = backend.server();
+        let on_server = |id: &str| self.server_of(s, id).name() == backend.name();
+        let private: Vec<Subnet> = Subnet::of_mask(&server.mask).into_iter()
+            .chain(s.sites.iter().filter(|(id, _)| on_server(id)).flat_map(|(_, site)| site.routes.iter().filter_map(|r| r.parse().ok())))
+            .collect();
+        let mut isolated: Vec<(&String, &Isolation)> = s.group_settings.iter().filter_map(|(g, settings)| Some((g, settings.isolation.as_ref()?))).collect();
+        isolated.sort_by_key(|(g, _)| *g);
+        let groups: Vec<(&Isolation, Vec<Subnet>)> = isolated.into_iter().map(|(group, isolation)| {
+            let mut ids: Vec<&String> = s.id_to_group.iter().filter(|(id, g)| *g == group && on_server(id)).map(|(id, _)| id).collect();
+            ids.sort_by_key(|id| s.keys.get(*id).map(|k| k.address.as_str()));
+            let sources = ids.into_iter().flat_map(|id| {
+                let address = s.keys.get(id).and_then(|k| k.address.parse().ok());
+                let routes = s.sites.get(id).into_iter().flat_map(|site| site.routes.iter().filter_map(|r| r.parse().ok()));
+                address.into_iter().chain(routes)
+            }).collect();
+            (isolation, sources)
+        }).collect();
+        let rules = isolation::rules(&groups, &private);
+        Ruleset { server: backend.name().to_string(), script: isolation::script(&server, &rules), rules }
+    }
+
+    /// Loads the isolation rules on every server where they changed since the
+    /// last script that loaded, or where the running chain no longer holds
+    /// them, e.g. after a container restart or a manual edit. Servers that never had any are left alone. Failures are logged and
+    /// retried on the next call; the peers are in place either way.
+    async fn enforce(&self, s: &StoredUsers) {
+        for backend in self.servers.iter() {
+            let ruleset = self.rules_of(s, backend);
+            let loaded = match backend.read_file(&backend.server().isolation_script()).await.ok() {
+                Some(script) => script == ruleset.script,
+                None if ruleset.rules.is_empty() => continue,
+                None => false,
+            };
+            if loaded && backend.live_rules().await.is_ok_and(|live| live.as_ref() == Some(&ruleset.rules)) {
+                continue;
+            }
+            match backend.load_rules(&ruleset.script).await {
+                Ok(()) => info!("Loaded {} isolation rules on {}", ruleset.rules.len(), backend.name()),
+                Err(e) => error!("Failed to load isolation rules on {}: {:#}", backend.name(), e),
+            }
+        }
+    }
+
+    /// Reloads the config file and templates and re-renders every share page.
+    /// Nothing is swapped in if the new config or a template is invalid.
+    pub async fn reload(&self) -> Result<ReloadReport> {
+        let mut next = config::reload_source()
+            .map_err(|e| AwgError::Validation(vec![FieldError::new("config", format!("{:#}", e))]))?;
+        let mut errors = next.validate();
+        let registry = match templates::load(&next.templates_dir) {
+            Ok(registry) => Some(registry),
+            Err(e) => {
+                errors.push(FieldError::new("templates_dir", format!("{:#}", e)));
+                None
+            }
+        };
+        let (true, Some(registry)) = (errors.is_empty(), registry) else {
+            return Err(AwgError::Validation(errors).into());
+        };
+        let restart_required = next.keep_restart_only(&config());
+
+        let s = self.stored.write().await;
+        config::replace(next);
+        templates::replace(registry);
+        for group in s.pages.keys() {
+            if let Err(e) = s.render_page(group).await {
+                error!("Failed to re-render page of {}: {:?}", group, e);
+            }
+        }
         drop(s);
+        if !restart_required.is_empty() {
+            warn!("Reloaded; changes to {} need a restart", restart_required.join(", "));
+        } else {
+            info!("Reloaded config and templates");
+        }
+        Ok(ReloadReport { restart_required })
+    }
 
+    /// Removes every user from every server.
+    pub async fn clear(&self) -> Result<()> {
+        let mut s = self.stored.write().await;
+        for backend in self.servers.iter() {
+            self.transact(backend, &mut s, async |tx, _s| {
+                drop_all(tx);
+                tx.apply().await
+            }).await?;
+        }
+        *s = StoredUsers::default();
+        tokio::fs::remove_dir_all(&config().served_dir).await.ok();
+        self.enforce(&s).await;
+        self.backup(&s).await;
+        drop(s);
+        Ok(())
     }
 }
 
-#[derive(Serialize)]
-pub struct GroupRecord {group: String, guid: String}
-
+/// Bytes received plus sent by each of `records`, by client id.
+fn transfer<'a>(records: impl Iterator<Item = &'a ClientTableRecord>) -> HashMap<String, u64> {
+    records.map(|r| {
+        let (recv, sent) = r.user_data.transfer();
+        (r.client_id.clone(), recv + sent)
+    }).collect()
+}
 
-#[derive(Serialize)]
-pub struct User {
-    pub uid: String,
-    pub name: String
+struct UpdatePlan {
+    client_id: String,
+    name: String,
+    old_group: Option<String>,
+    group: String,
+    device_limit: Option<usize>,
 }
 
+
+
 impl From<&ClientTableRecord> for User {
     fn from(record: &ClientTableRecord) -> Self {
-        Self {
+        User {
             uid: record.client_id.clone(),
             name: record.user_data.client_name.clone()
         }
     }
 }
 
-#[derive(Serialize)]
-pub struct UserStats {
-    uid: String,
-    name: String,
-    recv: String,
-    sent: String,
-    last_seen: String,
-    created: String
-}
-
 impl From<&ClientTableRecord> for UserStats {
     fn from(record: &ClientTableRecord) -> Self {
         Self {
@@ -0,0 +1,50 @@
+use std::{fmt, net::Ipv4Addr, str::FromStr};
+
+/// An IPv4 network such as `192.168.10.0/24`; a bare address is a `/32`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Subnet {
+    addr: u32,
+    len: u8,
+}
+
+impl Subnet {
+    fn mask(len: u8) -> u32 {
+        u32::MAX.checked_shl(32 - len as u32).unwrap_or(0)
+    }
+
+    pub fn overlaps(&self, other: &Subnet) -> bool {
+        let mask = Self::mask(self.len.min(other.len));
+        self.addr & mask == other.addr & mask
+    }
+
+    /// The peer subnet of a server with `mask`, e.g. `10.8.1.0/24` for `10.8.1.`.
+    pub fn of_mask(mask: &str) -> Option<Self> {
+        format!("{}0/24", mask).parse().ok()
+    }
+
+    /// The IPv4 entries of an `AllowedIPs` value.
+    pub fn list(allowed_ips: &str) -> Vec<Self> {
+        allowed_ips.split(',').filter_map(|s| s.trim().parse().ok()).collect()
+    }
+}
+
+impl FromStr for Subnet {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, len) = s.split_once('/').unwrap_or((s, "32"));
+        let addr: Ipv4Addr = addr.parse().map_err(|_| format!("{:?} is not an IPv4 subnet", s))?;
+        let len: u8 = len.parse().ok().filter(|l| *l <= 32).ok_or_else(|| format!("{:?} has an invalid prefix length", s))?;
+        let addr = u32::from(addr);
+        if addr & !Self::mask(len) != 0 {
+            return Err(format!("{:?} has host bits set; did you mean {}?", s, Subnet { addr: addr & Self::mask(len), len }));
+        }
+        Ok(Self { addr, len })
+    }
+}
+
+impl fmt::Display for Subnet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", Ipv4Addr::from(self.addr), self.len)
+    }
+}
@@ -0,0 +1,37 @@
+use std::sync::{Arc, RwLock};
+
+use anyhow::{Context, Result};
+use handlebars::Handlebars;
+use once_cell::sync::Lazy;
+use serde::Serialize;
+
+use crate::config::config;
+
+/// Client config template, rendered once per user.
+pub const CONFIG: &str = "config";
+/// Share page template, rendered once per group.
+pub const INDEX: &str = "index";
+
+static REGISTRY: Lazy<RwLock<Arc<Handlebars<'static>>>> = Lazy::new(|| {
+    RwLock::new(Arc::new(load(&config().templates_dir).unwrap_or_else(|e| panic!("{:#}", e))))
+});
+
+/// Compiles `config.hbs` and `index.hbs` from `dir`.
+pub fn load(dir: &str) -> Result<Handlebars<'static>> {
+    let mut h = Handlebars::new();
+    for name in [CONFIG, INDEX] {
+        let path = format!("{}/{}.hbs", dir, name);
+        h.register_template_file(name, &path).with_context(|| format!("Failed to load template {}", path))?;
+    }
+    Ok(h)
+}
+
+/// Swaps in `registry` for every render from now on.
+pub fn replace(registry: Handlebars<'static>) {
+    *REGISTRY.write().unwrap() = Arc::new(registry);
+}
+
+pub fn render<T: Serialize>(name: &str, data: &T) -> Result<String> {
+    let registry = REGISTRY.read().unwrap().clone();
+    Ok(registry.render(name, data)?)
+}
@@ -0,0 +1,230 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use tracing::{error, info, warn};
+
+use crate::{interactions::{backend::Backend, error::AwgError, client_table::{parse_client_table, ClientTableRecord}, wg0::{AwgInterfaceConf, AwgPeer}}};
+
+/// Deltas with more peer changes than this are applied with a full `wg syncconf`.
+const LIVE_DELTA_LIMIT: usize = 16;
+
+/// Peers added, updated or removed by a transaction.
+#[derive(Default)]
+pub struct PeerDelta {
+    pub set: Vec<AwgPeer>,
+    pub removed: Vec<String>,
+}
+
+impl PeerDelta {
+    pub fn between(before: &HashMap<String, AwgPeer>, after: &HashMap<String, AwgPeer>) -> Self {
+        let mut delta = Self::default();
+        for (key, peer) in after.iter() {
+            if before.get(key) != Some(peer) {
+                delta.set.push(peer.clone());
+            }
+        }
+        for key in before.keys() {
+            if !after.contains_key(key) {
+                delta.removed.push(key.clone());
+            }
+        }
+        delta
+    }
+
+    pub fn len(&self) -> usize {
+        self.set.len() + self.removed.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// A change to `wg0.conf` and `clientsTable` that is applied as a whole.
+///
+/// Both files are snapshotted on `begin`, the parsed copies are edited in
+/// memory and `apply` writes them, validates the result and syncs the interface.
+/// If anything fails the snapshot is written back.
+///
+/// Small deltas are pushed to the running interface peer by peer with `wg set`;
+/// bulk changes, or an interface that no longer matches the snapshot, get a full `wg syncconf`.
+pub struct Transaction {
+    pub backend: Backend,
+    wg_snapshot: String,
+    clients_snapshot: String,
+    snapshot_peers: HashMap<String, AwgPeer>,
+    /// Server public key when the transaction began.
+    key_snapshot: String,
+    applied: bool,
+    /// Set once the snapshot was restored, so it is restored only once.
+    rolled_back: bool,
+    /// Set by `apply_restart`, so a rollback restarts the interface too.
+    restarted: bool,
+    pub wg: AwgInterfaceConf,
+    pub clients_table: Vec<ClientTableRecord>,
+}
+
+impl Transaction {
+    pub async fn begin(backend: Backend) -> Result<Self> {
+        let server = backend.server();
+        let wg_snapshot = backend.read_file(&server.wg_conf()).await?;
+        let clients_snapshot = backend.read_file(&server.clients_table()).await?;
+        let public_key = backend.read_file(&server.server_public_key()).await?;
+        let wg = AwgInterfaceConf::parse(&wg_snapshot, public_key)?
+            .ok_or(AwgError::ConfigParse("wg0.conf".to_string()))?;
+        let clients_table = parse_client_table(&clients_snapshot)?;
+        Ok(Self { backend, wg_snapshot, clients_snapshot, snapshot_peers: wg.peers.clone(), key_snapshot: wg.public_key.clone(), applied: false, rolled_back: false, restarted: false, wg, clients_table })
+    }
+
+    /// Writes the edited files, validates them and syncs the interface.
+    /// On failure the snapshot is restored before the error is returned; a
+    /// failed restore is logged, the error returned is still the apply error.
+    pub async fn apply(&mut self) -> Result<()> {
+        self.applied = true;
+        if let Err(e) = self.try_apply().await {
+            error!("Failed to apply transaction: {:?}", e);
+            if let Err(re) = self.rollback().await {
+                error!("Failed to roll back: {:?}", re);
+            }
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Like [`apply`](Self::apply), but restarts the interface instead of
+    /// syncing peers, for `[Interface]` changes such as the obfuscation
+    /// parameters. If the restart fails, the interface is restarted again
+    /// from the restored snapshot, as it is by a later [`rollback`](Self::rollback).
+    pub async fn apply_restart(&mut self) -> Result<()> {
+        self.applied = true;
+        self.restarted = true;
+        if let Err(e) = self.write().await {
+            error!("Failed to apply transaction: {:?}", e);
+            // Nothing was restarted yet; the files alone need restoring.
+            self.rolled_back = true;
+            if let Err(re) = self.restore_files().await {
+                error!("Failed to roll back: {:?}", re);
+            }
+            return Err(e);
+        }
+        if let Err(e) = self.backend.restart_interface().await {
+            error!("Failed to restart the interface: {:?}", e);
+            match self.restore_restart().await {
+                Ok(()) => info!("Restarted the interface with the previous config"),
+                Err(re) => error!("Failed to restart the interface with the previous config: {:?}", re),
+            }
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    async fn restore_restart(&mut self) -> Result<()> {
+        self.rolled_back = true;
+        self.restore_files().await?;
+        self.backend.restart_interface().await
+    }
+
+    /// Writes and validates both files, and the server key files if the
+    /// keypair was changed.
+    async fn write(&self) -> Result<()> {
+        let server = self.backend.server();
+        self.backend.write_file(&server.clients_table(), &serde_json::to_string_pretty(&self.clients_table)?).await?;
+        self.backend.write_file(&server.wg_conf(), &self.wg.to_string()).await?;
+        if self.wg.public_key != self.key_snapshot {
+            let private_key = self.wg.private_key().ok_or(anyhow::anyhow!("wg0.conf has no PrivateKey"))?;
+            self.backend.write_file(&server.server_private_key(), private_key).await?;
+            self.backend.write_file(&server.server_public_key(), &self.wg.public_key).await?;
+        }
+        self.validate().await
+    }
+
+    async fn try_apply(&self) -> Result<()> {
+        self.write().await?;
+        let delta = PeerDelta::between(&self.snapshot_peers, &self.wg.peers);
+        if delta.is_empty() {
+            return Ok(());
+        }
+        if delta.len() <= LIVE_DELTA_LIMIT && !self.drifted().await {
+            match self.apply_live(&delta).await {
+                Ok(()) => return Ok(()),
+                Err(e) => warn!("Live peer update failed, falling back to syncconf: {:?}", e),
+            }
+        }
+        self.backend.sync_conf().await
+    }
+
+    async fn apply_live(&self, delta: &PeerDelta) -> Result<()> {
+        for key in delta.removed.iter() {
+            self.backend.remove_peer(key).await?;
+        }
+        for peer in delta.set.iter() {
+            self.backend.set_peer(peer).await?;
+        }
+        info!("Applied {} peer change(s) live", delta.len());
+        Ok(())
+    }
+
+    /// Whether the running interface holds a different peer set than the snapshot.
+    async fn drifted(&self) -> bool {
+        match self.backend.live_peers().await {
+            Ok(live) => {
+                let drifted = live.len() != self.snapshot_peers.len() || live.iter().any(|k| !self.snapshot_peers.contains_key(k));
+                if drifted {
+                    warn!("wg0 peers drifted from wg0.conf");
+                }
+                drifted
+            }
+            Err(e) => {
+                warn!("Failed to read live peers: {:?}", e);
+                true
+            }
+        }
+    }
+
+    async fn validate(&self) -> Result<()> {
+        let written = self.backend.read_file(&self.backend.server().wg_conf()).await?;
+        let parsed = AwgInterfaceConf::parse(&written, self.wg.public_key.clone())?
+            .ok_or(anyhow::anyhow!("Written wg0.conf has no interface"))?;
+        if parsed.peers != self.wg.peers || parsed.suspended != self.wg.suspended {
+            return Err(anyhow::anyhow!("Written wg0.conf peers do not match"));
+        }
+        self.backend.check_conf(&self.backend.server().wg_conf()).await?;
+        let clients = parse_client_table(&self.backend.read_file(&self.backend.server().clients_table()).await?)?;
+        if clients.len() != self.clients_table.len() {
+            return Err(anyhow::anyhow!("Written clientsTable does not match"));
+        }
+        Ok(())
+    }
+
+    /// Restores both files from the snapshot and brings the interface back to
+    /// it, restarting it if `apply_restart` did. Does nothing if neither apply
+    /// was called, or if the snapshot was already restored.
+    pub async fn rollback(&mut self) -> Result<()> {
+        if !self.applied || self.rolled_back {
+            return Ok(());
+        }
+        if self.restarted {
+            self.restore_restart().await?;
+            info!("Rolled back and restarted the interface");
+            return Ok(());
+        }
+        self.rolled_back = true;
+        self.restore_files().await?;
+        self.backend.sync_conf().await?;
+        info!("Rolled back");
+        Ok(())
+    }
+
+    async fn restore_files(&self) -> Result<()> {
+        warn!("Rolling back wg0.conf and clientsTable");
+        let server = self.backend.server();
+        self.backend.write_file(&server.clients_table(), &self.clients_snapshot).await?;
+        if self.wg.public_key != self.key_snapshot
+            && let Some(old) = AwgInterfaceConf::parse(&self.wg_snapshot, self.key_snapshot.clone())?
+            && let Some(private_key) = old.private_key() {
+            self.backend.write_file(&server.server_private_key(), private_key).await?;
+            self.backend.write_file(&server.server_public_key(), &self.key_snapshot).await?;
+        }
+        self.backend.write_file(&server.wg_conf(), &self.wg_snapshot).await
+    }
+}
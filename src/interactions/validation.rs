@@ -0,0 +1,93 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+use std::net::IpAddr;
+
+use crate::{config::config, interactions::subnet::Subnet};
+
+pub use simple_awg_api_types::{FieldError, Site};
+
+/// Checks a user or group name against the config keys `name_min_len` and
+/// `name_max_len` (in characters) and `name_extra_chars`, the characters
+/// allowed on top of letters and digits.
+pub fn check_label(field: &str, value: &str, errors: &mut Vec<FieldError>) {
+    let config = config();
+    let len = value.chars().count();
+    if len < config.name_min_len || len > config.name_max_len {
+        errors.push(FieldError::new(field, format!("must be {}-{} characters long", config.name_min_len, config.name_max_len)));
+    }
+    if value.trim() != value {
+        errors.push(FieldError::new(field, "must not start or end with whitespace"));
+    }
+    if let Some(c) = value.chars().find(|c| !c.is_alphanumeric() && !config.name_extra_chars.contains(*c)) {
+        errors.push(FieldError::new(field, format!("contains disallowed character {:?}", c)));
+    }
+}
+
+/// Checks that `value` is a WireGuard key: 32 bytes in base64.
+pub fn check_key(field: &str, value: &str, errors: &mut Vec<FieldError>) {
+    if STANDARD.decode(value).map_or(true, |b| b.len() != 32) {
+        errors.push(FieldError::new(field, "must be a base64 encoded 32 byte WireGuard key"));
+    }
+}
+
+/// Checks the routes and client `AllowedIPs` of a site peer. Overlaps with
+/// other peers are checked where those are known.
+pub fn check_site(prefix: &str, site: &Site, errors: &mut Vec<FieldError>) {
+    if site.routes.is_empty() {
+        errors.push(FieldError::new(format!("{prefix}site.routes"), "must list at least one subnet"));
+    }
+    let mut routes: Vec<Subnet> = vec![];
+    for route in site.routes.iter() {
+        match route.parse::<Subnet>() {
+            Ok(subnet) if routes.iter().any(|r| r.overlaps(&subnet)) => {
+                errors.push(FieldError::new(format!("{prefix}site.routes"), format!("{} overlaps another route of the site", route)));
+            }
+            Ok(subnet) => routes.push(subnet),
+            Err(e) => errors.push(FieldError::new(format!("{prefix}site.routes"), e)),
+        }
+    }
+    for ip in site.allowed_ips.iter() {
+        let valid = match ip.split_once('/') {
+            Some((addr, len)) => match (addr.parse::<IpAddr>(), len.parse::<u8>()) {
+                (Ok(IpAddr::V4(_)), Ok(len)) => len <= 32,
+                (Ok(IpAddr::V6(_)), Ok(len)) => len <= 128,
+                _ => false,
+            },
+            None => ip.parse::<IpAddr>().is_ok(),
+        };
+        if !valid {
+            errors.push(FieldError::new(format!("{prefix}site.allowed_ips"), format!("{:?} is not an address or subnet", ip)));
+        }
+    }
+}
+
+/// Turns a user name into something safe to offer as a `.conf` download.
+/// WireGuard derives the interface name from the file name, so it is also
+/// cut to the 15 characters Linux allows for interface names.
+pub fn conf_file_name(name: &str) -> String {
+    let stem: String = name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || "_=+.-".contains(c) { c } else { '_' })
+        .take(15)
+        .collect();
+    let stem = stem.trim_start_matches('.');
+    if stem.is_empty() {
+        "wg0.conf".to_string()
+    } else {
+        format!("{stem}.conf")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conf_file_names_are_safe() {
+        assert_eq!(conf_file_name("alice"), "alice.conf");
+        assert_eq!(conf_file_name("../etc/passwd"), "_etc_passwd.conf");
+        assert_eq!(conf_file_name("<b>\"x\" & y</b>"), "_b__x____y__b_.conf");
+        assert_eq!(conf_file_name("a-very-long-user-name"), "a-very-long-use.conf");
+        assert_eq!(conf_file_name("Ärger"), "_rger.conf");
+        assert_eq!(conf_file_name("..."), "wg0.conf");
+    }
+}
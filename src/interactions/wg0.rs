@@ -1,19 +1,25 @@
-use std::collections::HashMap;
+use std::{collections::{HashMap, HashSet}, fmt};
 
-use serde::{Deserialize, Serialize};
+use serde::Deserialize;
 use tracing::{error, info, warn};
 
-use crate::interactions::shared::command_in_docker;
+use crate::{interactions::{backend::Backend, error::AwgError, obfuscation::ObfuscationParams}};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct AwgInterfaceConf {
     pub interface: String,
     pub public_key: String,
     pub parsed_iface: AWGInterfaceData,
     pub peers: HashMap<String, AwgPeer>,
+    /// Peers commented out of the config: they keep their address, but
+    /// `wg-quick strip` leaves them out of the running interface.
+    pub suspended: HashMap<String, AwgPeer>,
 }
 
-#[derive(Debug)]
+/// Section name the parser gives a commented-out `#[Peer]`.
+const SUSPENDED_PEER: &str = "#Peer";
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct AwgPeer {
     pub public_key: String,
     pub preshared_key: String,
@@ -42,15 +48,41 @@ impl AwgPeer {
             allowed_ips: allowed_ips?
         })
     }
+}
 
-    pub fn to_string(&self) -> String {
-        format!("[Peer]\nPublicKey = {}\nPresharedKey = {}\nAllowedIPs = {}", self.public_key, self.preshared_key, self.allowed_ips)
+impl fmt::Display for AwgPeer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[Peer]\nPublicKey = {}\nPresharedKey = {}\nAllowedIPs = {}", self.public_key, self.preshared_key, self.allowed_ips)
     }
 }
 
 
 
 impl AwgInterfaceConf {
+    /// Whether `public_key` is a peer of the config, suspended or not.
+    pub fn has_peer(&self, public_key: &str) -> bool {
+        self.peers.contains_key(public_key) || self.suspended.contains_key(public_key)
+    }
+
+    /// Every peer of the config, suspended ones included.
+    pub fn all_peers(&self) -> impl Iterator<Item = &AwgPeer> {
+        self.peers.values().chain(self.suspended.values())
+    }
+
+    /// Comments peer `public_key` out. False if it isn't an active peer.
+    pub fn suspend(&mut self, public_key: &str) -> bool {
+        let Some(peer) = self.peers.remove(public_key) else { return false };
+        self.suspended.insert(public_key.to_string(), peer);
+        true
+    }
+
+    /// Makes suspended peer `public_key` active again. False if it isn't suspended.
+    pub fn resume(&mut self, public_key: &str) -> bool {
+        let Some(peer) = self.suspended.remove(public_key) else { return false };
+        self.peers.insert(public_key.to_string(), peer);
+        true
+    }
+
     fn try_parse_id(p: &str) -> Option<u32> {
         let id = p
             .split('/')
@@ -62,9 +94,26 @@ impl AwgInterfaceConf {
             ;
         Some(id)
     }
+    /// Host ids of the peer addresses in use.
+    pub fn used_ids(&self) -> HashSet<u32> {
+        self.all_peers().filter_map(|p| Self::try_parse_id(&p.allowed_ips)).collect()
+    }
+
+    /// The lowest host id that is neither in use nor in `skip`.
+    pub fn free_id(&self, skip: &HashSet<u32>) -> Option<u32> {
+        let used = self.used_ids();
+        (2..=254).find(|id| !used.contains(id) && !skip.contains(id))
+    }
+
+    /// Host ids neither in use nor in `skip`.
+    pub fn free_count(&self, skip: &HashSet<u32>) -> u32 {
+        let used = self.used_ids();
+        (2..=254).filter(|id| !used.contains(id) && !skip.contains(id)).count() as u32
+    }
+
     pub fn get_last_id(&self) -> u32 {
         let mut last : u32 = 1;
-        for (_, peer) in self.peers.iter() {
+        for peer in self.all_peers() {
             let Some(id) = Self::try_parse_id(&peer.allowed_ips) else {
                 warn!("Failed to parse id: {}", peer.allowed_ips);
                 continue;
@@ -73,23 +122,61 @@ impl AwgInterfaceConf {
         }
         last
     }
-    pub fn to_string(&self) -> String {
-        format!("[Interface]\n{}\n\n{}\n\n", self.interface, self.peers.values().map(|p| p.to_string()).collect::<Vec<String>>().join("\n\n"))
+
+    /// Writes `p` into the `[Interface]` lines, replacing the old values in
+    /// place and appending the ones that were missing.
+    pub fn set_obfuscation(&mut self, p: &ObfuscationParams) -> anyhow::Result<()> {
+        self.set_values(vec![
+            ("Jc", p.jc.to_string()), ("Jmin", p.jmin.to_string()), ("Jmax", p.jmax.to_string()), ("S1", p.s1.to_string()), ("S2", p.s2.to_string()),
+            ("H1", p.h1.clone()), ("H2", p.h2.clone()), ("H3", p.h3.clone()), ("H4", p.h4.clone()),
+        ])
+    }
+
+    /// Puts a new server keypair into the `[Interface]` lines.
+    pub fn set_keypair(&mut self, private_key: &str, public_key: &str) -> anyhow::Result<()> {
+        self.set_values(vec![("PrivateKey", private_key.to_string())])?;
+        self.public_key = public_key.to_string();
+        Ok(())
     }
 
+    /// The server's private key from the `[Interface]` lines.
+    pub fn private_key(&self) -> Option<&str> {
+        self.interface.lines()
+            .filter_map(|l| l.split_once('='))
+            .find(|(k, _)| k.trim().eq_ignore_ascii_case("PrivateKey"))
+            .map(|(_, v)| v.trim())
+    }
 
+    fn set_values(&mut self, mut missing: Vec<(&str, String)>) -> anyhow::Result<()> {
+        let mut lines: Vec<String> = self.interface.lines().map(|line| {
+            let key = line.split_once('=').map(|(k, _)| k.trim()).unwrap_or_default();
+            match missing.iter().position(|(k, _)| k.eq_ignore_ascii_case(key)) {
+                Some(i) => {
+                    let (k, v) = missing.remove(i);
+                    format!("{} = {}", k, v)
+                }
+                None => line.to_string(),
+            }
+        }).collect();
+        lines.extend(missing.into_iter().map(|(k, v)| format!("{} = {}", k, v)));
+        let interface = lines.join("\n");
+        self.parsed_iface = AWGInterfaceData::parse(&interface).ok_or(AwgError::ConfigParse("[Interface] of wg0.conf".to_string()))?;
+        self.interface = interface;
+        Ok(())
+    }
 
-    pub async fn from_docker() -> anyhow::Result<Option<Self>> {
-        let output = command_in_docker(&["cat", "/opt/amnezia/awg/wg0.conf"]).await?;
+    pub async fn fetch(backend: &Backend) -> anyhow::Result<Option<Self>> {
+        let data = backend.read_file(&backend.server().wg_conf()).await?;
         info!("Got wg0.conf");
-        let data = String::from_utf8_lossy(&output.stdout);
-        let pk = command_in_docker(&["cat", "/opt/amnezia/awg/wireguard_server_public_key.key"]).await?;
+        let public_key = backend.read_file(&backend.server().server_public_key()).await?;
         info!("Got public key");
-        let public_key = String::from_utf8_lossy(&pk.stdout).into_owned();
-        
+        Self::parse(&data, public_key)
+    }
 
+    pub fn parse(data: &str, public_key: String) -> anyhow::Result<Option<Self>> {
         let mut interface_lines: Vec<String> = Vec::new();
         let mut peers = HashMap::new();
+        let mut suspended = HashMap::new();
 
         let mut current_section = String::new();
         let mut current_lines: Vec<String> = Vec::new();
@@ -104,7 +191,14 @@ impl AwgInterfaceConf {
                         } else {
                             warn!("Failed to parse peer section!");
                         }
-                        
+
+                    }
+                    SUSPENDED_PEER => {
+                        if let Some(peer) = AwgPeer::parse_str(lines) {
+                            suspended.insert(peer.public_key.clone(), peer);
+                        } else {
+                            warn!("Failed to parse suspended peer section!");
+                        }
                     }
                     _ => {}
                 }
@@ -113,7 +207,19 @@ impl AwgInterfaceConf {
 
         for line in data.lines() {
             let line = line.trim();
-            if line.is_empty() || line.starts_with('#') {
+            // A commented-out `#[Peer]` and the commented key lines after it are a suspended peer.
+            if let Some(commented) = line.strip_prefix('#') {
+                let commented = commented.trim();
+                if commented == "[Peer]" {
+                    store_section(&current_section, &current_lines);
+                    current_section = SUSPENDED_PEER.to_string();
+                    current_lines.clear();
+                } else if current_section == SUSPENDED_PEER && commented.contains('=') {
+                    current_lines.push(commented.to_string());
+                }
+                continue;
+            }
+            if line.is_empty() {
                 continue;
             }
 
@@ -126,21 +232,29 @@ impl AwgInterfaceConf {
             }
         }
         store_section(&current_section, &current_lines);
-        
+
         if interface_lines.is_empty() {
             return Ok(None)
         }
 
         let interface = interface_lines.join("\n");
         Ok(Some(Self {
-            public_key,
-            parsed_iface: AWGInterfaceData::from_str(&interface).ok_or(anyhow::anyhow!("Failed to parse wg0.conf"))?,
+            public_key: public_key.trim().to_string(),
+            parsed_iface: AWGInterfaceData::parse(&interface).ok_or(AwgError::ConfigParse("[Interface] of wg0.conf".to_string()))?,
             interface,
             peers,
+            suspended,
         }))
     }
 }
 
+impl fmt::Display for AwgInterfaceConf {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let suspended = self.suspended.values().map(|p| p.to_string().lines().map(|l| format!("#{}", l)).collect::<Vec<_>>().join("\n"));
+        write!(f, "[Interface]\n{}\n\n{}\n\n", self.interface, self.peers.values().map(|p| p.to_string()).chain(suspended).collect::<Vec<String>>().join("\n\n"))
+    }
+}
+
 
 #[allow(unused)]
 #[derive(Debug, Deserialize, Clone)]
@@ -154,59 +268,57 @@ pub struct AWGInterfaceData {
     pub h1: String,
     pub h2: String,
     pub h3: String,
-    pub h4: String
-}
-
-#[derive(Default)]
-struct AWGRaw<'a> {
-    pub map: HashMap<&'a str, &'a str>,
-    pub last_allowed_ip: Option<String>,
-    pub public_key: Option<String>,
-    pub port: Option<String>
+    pub h4: String,
+    // AmneziaWG 1.5/2.0 parameters, absent on older servers.
+    pub s3: Option<String>,
+    pub s4: Option<String>,
+    pub i1: Option<String>,
+    pub i2: Option<String>,
+    pub i3: Option<String>,
+    pub i4: Option<String>,
+    pub i5: Option<String>,
+    pub j1: Option<String>,
+    pub j2: Option<String>,
+    pub j3: Option<String>,
+    pub itime: Option<String>,
 }
 
 impl AWGInterfaceData {
-    // pub async fn from_docker() -> anyhow::Result<Option<Self>> {
-    //     todo!("deprecated");
-    //     let r = command_in_docker(&["wg"]).await?;
-    //     let data = String::from_utf8_lossy(&r.stdout);
-    //     Ok(Some(AWGInterfaceData::from_str(&data).ok_or(anyhow::anyhow!("Failed to parse data"))?))
-    // } 
-    fn raw_from_str(s: &str) -> AWGRaw {
-        let mut raw = AWGRaw::default();
-        
-        raw
-    }
-
-    pub fn from_str(s: &str) -> Option<Self> {
-        let raw = Self::raw_from_str(s);
-
-        // let mut last_allowed_ip = None;
-        // let mut public_key = None;
-        // let mut port = None;
+    pub fn parse(s: &str) -> Option<Self> {
         let mut map = HashMap::new();
 
         for line in s.lines() {
             let line = line.trim();
-            if let Some((key, value)) = line.split_once(':') {
+            if let Some((key, value)) = line.split_once('=') {
                 let key = key.trim();
                 let value = value.trim();
                 map.insert(key.to_lowercase(), value);
             }
         }
+        let opt = |key: &str| map.get(key).filter(|v| !v.is_empty()).map(|v| v.to_string());
 
-        let port = raw.port?;
         Some(AWGInterfaceData {
-            port,
-            jc: raw.map.get("jc")?.parse().ok()?,
-            jmin: raw.map.get("jmin")?.parse().ok()?,
-            jmax: raw.map.get("jmax")?.parse().ok()?,
-            s1: raw.map.get("s1")?.parse().ok()?,
-            s2: raw.map.get("s2")?.parse().ok()?,
-            h1: raw.map.get("h1")?.parse().ok()?,
-            h2: raw.map.get("h2")?.parse().ok()?,
-            h3: raw.map.get("h3")?.parse().ok()?,
-            h4: raw.map.get("h4")?.parse().ok()?,
+            port: map.get("listenport")?.parse().ok()?,
+            jc: map.get("jc")?.parse().ok()?,
+            jmin: map.get("jmin")?.parse().ok()?,
+            jmax: map.get("jmax")?.parse().ok()?,
+            s1: map.get("s1")?.parse().ok()?,
+            s2: map.get("s2")?.parse().ok()?,
+            h1: map.get("h1")?.parse().ok()?,
+            h2: map.get("h2")?.parse().ok()?,
+            h3: map.get("h3")?.parse().ok()?,
+            h4: map.get("h4")?.parse().ok()?,
+            s3: opt("s3"),
+            s4: opt("s4"),
+            i1: opt("i1"),
+            i2: opt("i2"),
+            i3: opt("i3"),
+            i4: opt("i4"),
+            i5: opt("i5"),
+            j1: opt("j1"),
+            j2: opt("j2"),
+            j3: opt("j3"),
+            itime: opt("itime"),
         })
     }
 }
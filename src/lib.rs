@@ -0,0 +1,4 @@
+pub mod util;
+pub mod config;
+pub mod interactions;
+pub mod api;
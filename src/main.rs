@@ -1,47 +1,88 @@
+use std::{path::PathBuf, process::ExitCode};
+
 use anyhow::Result;
-use once_cell::sync::OnceCell;
+use clap::Parser;
 use tracing::*;
-use axum::{body::Body, response::IntoResponse, routing::{delete, get, post}};
-use crate::{api::*, interactions::shared::{sync_wg, AppState}, util::middleware};
-
-mod util;
-mod interactions;
-mod api;
-
-env_config!(
-    ".env" => ENV = Env {
-        container: String = "amnezia-awg".to_string(),
-        addr: String = "0.0.0.0:8080".to_string(),
-        host: String,
-        dns: String,
-        keepalive: String,
-        mask: String,
-        stored_file: String,
+use handlebars::Handlebars;
+use simple_awg_api::{api, config::{self, config, Config}, interactions::{schedule::{enforce_on_schedule, rotate_on_schedule, sample_on_schedule}, shared::AppState, templates}, layer_with_unique_span, util::middleware};
+
+#[derive(Parser)]
+struct Args {
+    /// TOML config file. Env vars override its values.
+    #[arg(long, env = "AWG_CONFIG")]
+    config: Option<PathBuf>,
+    /// Print the effective config as TOML, check it and exit.
+    #[arg(long)]
+    print_config: bool,
+}
+
+fn load(args: &Args) -> Result<(Config, Handlebars<'static>)> {
+    let config = Config::load(args.config.as_deref())?;
+    if args.print_config {
+        print!("{}", toml::to_string_pretty(&config)?);
     }
-);
+    let mut errors: Vec<String> = config.validate().into_iter().map(|e| format!("{}: {}", e.field, e.message)).collect();
+    let registry = templates::load(&config.templates_dir).map_err(|e| errors.push(format!("templates_dir: {:#}", e)));
+    match registry {
+        Ok(registry) if errors.is_empty() => Ok((config, registry)),
+        _ => Err(anyhow::anyhow!("Invalid config:\n  {}", errors.join("\n  "))),
+    }
+}
 
+/// Reloads config and templates on every SIGHUP.
+fn reload_on_sighup(state: AppState) -> Result<()> {
+    let mut hangup = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())?;
+    tokio::spawn(async move {
+        while hangup.recv().await.is_some() {
+            info!("SIGHUP received, reloading");
+            if let Err(e) = state.reload().await {
+                error!("Reload rejected, keeping the running config: {:#}", e);
+            }
+        }
+    });
+    Ok(())
+}
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    tracing_subscriber::fmt::init();
+async fn serve() -> Result<()> {
     let state = AppState::new();
 
     state.fetch_users().await?;
-    let router = axum::Router::new()
-        .route("/users", get(user_list))
-        .route("/users", delete(clear))
-        .route("/users", post(create_users))
-        .route("/stats", get(users_stats))
-        .route("/user", post(create_user))
-        .route("/user", delete(delete_user))
-        .route("/groups", get(groups))
-        .route("/id", get(last_id))
+    state.enforce_isolation().await;
+    reload_on_sighup(state.clone())?;
+    rotate_on_schedule(state.clone());
+    enforce_on_schedule(state.clone());
+    sample_on_schedule(state.clone());
+    let router = api::openapi::router()
         .layer(axum::middleware::from_fn(layer_with_unique_span!("request ")))
         .layer(axum::middleware::from_fn(middleware::logging_middleware))
         .with_state(state);
 
-    info!("Listening on {}", ENV.addr);
-    let listener = tokio::net::TcpListener::bind(ENV.addr.clone()).await?;
+    let addr = config().addr.clone();
+    info!("Listening on {}", addr);
+    let listener = tokio::net::TcpListener::bind(addr).await?;
     axum::serve(listener, router).await?;
     Ok(())
 }
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    tracing_subscriber::fmt::init();
+    let args = Args::parse();
+    let (config, registry) = match load(&args) {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            eprintln!("{:#}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    if args.print_config {
+        return ExitCode::SUCCESS;
+    }
+    config::init(config, args.config);
+    templates::replace(registry);
+    if let Err(e) = serve().await {
+        error!("{:?}", e);
+        return ExitCode::FAILURE;
+    }
+    ExitCode::SUCCESS
+}
@@ -1,6 +1,6 @@
 use axum::{
     body::Body,
-    http::Request,
+    http::{HeaderValue, Request},
     middleware::Next,
     response::Response,
 };
@@ -15,6 +15,15 @@ pub async fn logging_middleware(req: Request<Body>, next: Next) -> Response {
     response
 }
 
+/// Marks responses of the pre-`/v1` routes as deprecated.
+pub async fn deprecation_middleware(req: Request<Body>, next: Next) -> Response {
+    let mut response = next.run(req).await;
+    let headers = response.headers_mut();
+    headers.insert("Deprecation", HeaderValue::from_static("true"));
+    headers.insert("Link", HeaderValue::from_static("</v1/users>; rel=\"successor-version\""));
+    response
+}
+
 #[macro_export]
 macro_rules! make_unique_span {
     ($name:ident) => {
@@ -1,2 +1 @@
-pub mod middleware;
-pub mod env;
\ No newline at end of file
+pub mod middleware;
\ No newline at end of file
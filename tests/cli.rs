@@ -0,0 +1,59 @@
+use std::process::Output;
+
+use simple_awg_api::{api, config::ServerConfig, interactions::{backend::Backend, shared::AppState}};
+use simple_awg_api_client::types::UserResource;
+
+/// Serves the API on a random port, backed by in-memory containers, and
+/// returns its base URL.
+async fn server_with_backends(backends: Vec<Backend>) -> String {
+    let stored = std::env::temp_dir().join(format!("{}.save", uuid::Uuid::new_v4().simple()));
+    let state = AppState::with_backends(backends, &stored.to_string_lossy());
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, api::openapi::router().with_state(state)).await.unwrap();
+    });
+    format!("http://{}", addr)
+}
+
+/// Runs `awg-admin` with `args` against the API at `url`.
+async fn admin(url: &str, args: &[&str]) -> Output {
+    tokio::process::Command::new(env!("CARGO_BIN_EXE_awg-admin"))
+        .args(args)
+        .env("AWG_API_URL", url)
+        .output()
+        .await
+        .unwrap()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[tokio::test]
+async fn exit_codes_follow_the_error() {
+    let fra = ServerConfig { host: "fra.example.com".to_string(), mask: "10.8.2.".to_string(), ..Default::default() };
+    let url = server_with_backends(vec![Backend::fake(), Backend::fake_named("fra", fra)]).await;
+
+    let output = admin(&url, &["--json", "user", "add", "alice", "--group", "office", "--server", "fra"]).await;
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    let alice: UserResource = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!((alice.name.as_str(), alice.server.as_deref()), ("alice", Some("fra")));
+    let output = admin(&url, &["user", "show", &alice.id]).await;
+    assert_eq!(output.status.code(), Some(0));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("alice"));
+
+    let output = admin(&url, &["user", "show", "no-such-user"]).await;
+    assert_eq!(output.status.code(), Some(3));
+
+    let output = admin(&url, &["user", "add", "", "--group", "office"]).await;
+    assert_eq!(output.status.code(), Some(4));
+    assert!(stderr(&output).contains("name"));
+
+    let output = admin(&url, &["user", "add", "bob"]).await;
+    assert_eq!(output.status.code(), Some(2));
+
+    // Nothing listens on the discard port.
+    let output = admin("http://127.0.0.1:9", &["servers"]).await;
+    assert_eq!(output.status.code(), Some(1));
+}
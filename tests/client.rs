@@ -0,0 +1,609 @@
+use std::time::Duration;
+
+use simple_awg_api::{api, config::{config, ServerConfig}, interactions::{backend::Backend, shared::AppState}};
+use simple_awg_api_client::{types::{AwgCompat, CreateRequest, GroupSettings, Isolation, Job, JobStatus, PlacementPolicy, PlacementReason, ListQuery, Site, StatsQuery, UpdateRequest}, Client, Error, StatusCode};
+
+/// Serves the API on a random port, backed by an in-memory container.
+async fn server() -> Client {
+    server_with(Backend::fake()).await
+}
+
+async fn server_with(backend: Backend) -> Client {
+    server_with_backends(vec![backend]).await
+}
+
+async fn server_with_backends(backends: Vec<Backend>) -> Client {
+    let stored = std::env::temp_dir().join(format!("{}.save", uuid::Uuid::new_v4().simple()));
+    let state = AppState::with_backends(backends, &stored.to_string_lossy());
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, api::openapi::router().with_state(state)).await.unwrap();
+    });
+    Client::new(&format!("http://{}", addr)).unwrap()
+}
+
+fn create(name: &str, group: &str) -> CreateRequest {
+    CreateRequest { name: name.to_string(), group: group.to_string(), ..Default::default() }
+}
+
+/// Polls batch job `id` until it is no longer running.
+async fn finished(client: &Client, id: &str) -> Job {
+    loop {
+        let job = client.job(id).await.unwrap();
+        if job.status != JobStatus::Running {
+            return job;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+}
+
+#[tokio::test]
+async fn user_lifecycle() {
+    let client = server().await;
+
+    let user = client.create_user(&create("alice", "office")).await.unwrap();
+    assert_eq!(user.name, "alice");
+    assert_eq!(user.group.as_deref(), Some("office"));
+    assert_eq!(client.user(&user.id).await.unwrap().name, "alice");
+
+    let update = UpdateRequest { name: Some("alice2".to_string()), group: Some("home".to_string()), ..Default::default() };
+    let user = client.update_user(&user.id, &update).await.unwrap();
+    assert_eq!(user.name, "alice2");
+    assert_eq!(user.group.as_deref(), Some("home"));
+
+    let page = client.list_users(&ListQuery::default()).await.unwrap();
+    assert_eq!(page.total, 1);
+    let page = client.group_users("home", &ListQuery::default()).await.unwrap();
+    assert_eq!(page.items[0].id, user.id);
+    let groups = client.groups().await.unwrap();
+    assert!(groups.iter().any(|g| g.group == "home" && Some(&g.guid) == user.guid.as_ref()));
+    let rotated = client.rotate_link("home").await.unwrap();
+    assert_ne!(Some(&rotated.guid), user.guid.as_ref());
+    assert_eq!(client.user(&user.id).await.unwrap().guid, Some(rotated.guid));
+    assert_eq!(client.stats(&StatsQuery { group: Some("home".to_string()) }).await.unwrap().len(), 1);
+    assert_eq!(client.last_id().await.unwrap(), 2);
+    assert!(client.reload().await.unwrap().restart_required.is_empty());
+
+    client.delete_user(&user.id).await.unwrap();
+    assert_eq!(client.list_users(&ListQuery::default()).await.unwrap().total, 0);
+}
+
+#[tokio::test]
+async fn errors_are_typed() {
+    let client = server().await;
+
+    let err = client.user("no/such+id=").await.unwrap_err();
+    assert_eq!(err.status(), Some(StatusCode::NOT_FOUND));
+    assert_eq!(err.code(), Some("not_found"));
+
+    let Error::Api { status, body } = client.create_user(&create("", "office")).await.unwrap_err() else {
+        panic!("expected an API error");
+    };
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body.fields[0].field, "name");
+
+    client.create_user(&create("bob", "office")).await.unwrap();
+    let err = client.create_user(&create("bob", "office")).await.unwrap_err();
+    assert_eq!(err.status(), Some(StatusCode::UNPROCESSABLE_ENTITY));
+    assert_eq!(err.code(), Some("validation"));
+}
+
+#[tokio::test]
+async fn failed_writes_are_rolled_back() {
+    let backend = Backend::fake();
+    let Backend::Fake(fake) = &backend else { unreachable!() };
+    let fake = fake.clone();
+    let client = server_with(backend).await;
+
+    let alice = client.create_user(&create("alice", "office")).await.unwrap();
+    let page = format!("{}/{}/index.html", config().served_dir, alice.guid.as_deref().unwrap());
+    let files = || (fake.read_file(&fake.server.wg_conf()).unwrap(), fake.read_file(&fake.server.clients_table()).unwrap());
+    let before = (files(), client.list_users(&ListQuery::default()).await.unwrap().total, std::fs::read_to_string(&page).unwrap());
+
+    fake.fail_next("check_conf");
+    assert!(client.create_user(&create("bob", "office")).await.is_err());
+    // Restored once, by the transaction itself.
+    assert_eq!(fake.calls("sync_conf"), 1);
+    // A rollback that fails too still reports why the write failed.
+    fake.fail_next("check_conf");
+    fake.fail_next("sync_conf");
+    let Error::Api { body, .. } = client.create_user(&create("carol", "lab")).await.unwrap_err() else { panic!() };
+    assert!(body.message.contains("check_conf failed"), "{}", body.message);
+    fake.sync_conf().unwrap();
+
+    let after = (files(), client.list_users(&ListQuery::default()).await.unwrap().total, std::fs::read_to_string(&page).unwrap());
+    assert_eq!(before, after);
+    assert!(client.groups().await.unwrap().iter().all(|g| g.group != "lab"));
+    assert_eq!(fake.live_peers().len(), 1);
+}
+
+#[tokio::test]
+async fn small_changes_are_applied_live() {
+    let backend = Backend::fake();
+    let Backend::Fake(fake) = &backend else { unreachable!() };
+    let fake = fake.clone();
+    let client = server_with(backend).await;
+
+    let alice = client.create_user(&create("alice", "office")).await.unwrap();
+    let bob = client.create_user(&create("bob", "office")).await.unwrap();
+    client.delete_user(&bob.id).await.unwrap();
+    assert_eq!((fake.calls("set_peer"), fake.calls("remove_peer"), fake.calls("sync_conf")), (2, 1, 0));
+    assert_eq!(fake.live_peers(), [alice.id.clone()].into());
+
+    // A peer missing from the interface means wg0.conf can't be trusted to
+    // match it, so the next change syncs everything.
+    fake.remove_peer(&alice.id).unwrap();
+    let carol = client.create_user(&create("carol", "office")).await.unwrap();
+    assert_eq!((fake.calls("set_peer"), fake.calls("sync_conf")), (2, 1));
+    assert_eq!(fake.live_peers(), [alice.id.clone(), carol.id].into());
+
+    // More changes than are worth pushing one by one.
+    let batch: Vec<_> = (0..20).map(|i| create(&format!("user{}", i), "bulk")).collect();
+    let job = finished(&client, &client.create_users(&batch).await.unwrap().id).await;
+    assert_eq!(job.status, JobStatus::Completed);
+    assert_eq!((fake.calls("set_peer"), fake.calls("sync_conf")), (2, 2));
+    assert_eq!(fake.live_peers().len(), 22);
+}
+
+#[tokio::test]
+async fn suspended_users_are_taken_off_live() {
+    let backend = Backend::fake();
+    let Backend::Fake(fake) = &backend else { unreachable!() };
+    let fake = fake.clone();
+    let client = server_with(backend).await;
+
+    let alice = client.create_user(&create("alice", "office")).await.unwrap();
+    let bob = client.create_user(&create("bob", "office")).await.unwrap();
+    let suspended = client.suspend_user(&alice.id).await.unwrap();
+    assert!(suspended.suspended);
+    assert_eq!((fake.calls("set_peer"), fake.calls("remove_peer"), fake.calls("sync_conf")), (2, 1, 0));
+    assert_eq!(fake.live_peers(), [bob.id.clone()].into());
+    let wg_conf = fake.read_file(&fake.server.wg_conf()).unwrap();
+    assert!(wg_conf.contains(&format!("#PublicKey = {}", alice.id)));
+
+    // The suspended peer keeps its address, so a new user can't take it.
+    let carol = client.create_user(&create("carol", "office")).await.unwrap();
+    assert_ne!(carol.locations[0].address, alice.locations[0].address);
+    assert_eq!(client.rotate_keys(&alice.id).await.unwrap_err().status(), Some(StatusCode::CONFLICT));
+
+    let resumed = client.resume_user(&alice.id).await.unwrap();
+    assert!(!resumed.suspended);
+    assert_eq!((fake.calls("set_peer"), fake.calls("remove_peer"), fake.calls("sync_conf")), (4, 1, 0));
+    assert_eq!(fake.live_peers(), [alice.id.clone(), bob.id, carol.id].into());
+    assert!(!fake.read_file(&fake.server.wg_conf()).unwrap().contains("#PublicKey"));
+    assert_eq!(client.suspend_user("nobody").await.unwrap_err().status(), Some(StatusCode::NOT_FOUND));
+}
+
+#[tokio::test]
+async fn concurrent_creates_share_a_write() {
+    let backend = Backend::fake();
+    let Backend::Fake(fake) = &backend else { unreachable!() };
+    let fake = fake.clone();
+    let client = server_with(backend).await;
+
+    // Both `box` requests pass validation on their own and only clash once
+    // batched; the bad name is turned away before it is queued.
+    let fixed = |name: &str| CreateRequest { address: Some("10.8.1.9".to_string()), ..create(name, "office") };
+    let reqs = [create("alice", "office"), create("bob", "office"), fixed("box1"), fixed("box2"), create("", "office")];
+    let (a, b, c, d, bad) = tokio::join!(
+        client.create_user(&reqs[0]),
+        client.create_user(&reqs[1]),
+        client.create_user(&reqs[2]),
+        client.create_user(&reqs[3]),
+        client.create_user(&reqs[4]),
+    );
+    assert_eq!(fake.calls("check_conf"), 1);
+    assert_eq!(bad.unwrap_err().code(), Some("validation"));
+    let (a, b) = (a.unwrap(), b.unwrap());
+    assert_eq!((a.name.as_str(), b.name.as_str()), ("alice", "bob"));
+    assert_ne!(a.id, b.id);
+    let (ok, clash) = if c.is_ok() { (c, d) } else { (d, c) };
+    assert_eq!(ok.unwrap().locations[0].address.as_deref(), Some("10.8.1.9"));
+    assert_eq!(clash.unwrap_err().code(), Some("conflict"));
+    assert_eq!(client.list_users(&ListQuery::default()).await.unwrap().total, 3);
+}
+
+#[tokio::test]
+async fn batch_job_completes() {
+    let client = server().await;
+
+    let batch: Vec<_> = (0..5).map(|i| create(&format!("user{}", i), "batch")).collect();
+    let job = finished(&client, &client.create_users(&batch).await.unwrap().id).await;
+    assert_eq!(job.status, JobStatus::Completed);
+    assert_eq!(job.done, 5);
+    assert_eq!(client.group_users("batch", &ListQuery::default()).await.unwrap().total, 5);
+
+    client.clear().await.unwrap();
+    assert_eq!(client.list_users(&ListQuery::default()).await.unwrap().total, 0);
+}
+
+#[tokio::test]
+async fn regenerate_picks_up_server_changes() {
+    let backend = Backend::fake();
+    let client = server_with(backend.clone()).await;
+    let user = client.create_user(&create("carol", "ops")).await.unwrap();
+    assert!(client.regenerate_configs(true).await.unwrap().changed.is_empty());
+
+    let wg_conf = backend.server().wg_conf();
+    let conf = backend.read_file(&wg_conf).await.unwrap().replace("ListenPort = 51820", "ListenPort = 51999");
+    backend.write_file(&wg_conf, &conf).await.unwrap();
+
+    let report = client.regenerate_configs(true).await.unwrap();
+    assert_eq!(report.changed.len(), 1);
+    assert_eq!(report.changed[0].id, user.id);
+    assert!(report.changed[0].diff.contains("+Endpoint = "));
+    assert!(report.changed[0].diff.contains(":51999"));
+    assert!(!report.changed[0].diff.contains("PrivateKey"));
+
+    assert_eq!(client.regenerate_configs(false).await.unwrap().changed.len(), 1);
+    assert!(client.regenerate_configs(true).await.unwrap().changed.is_empty());
+}
+
+#[tokio::test]
+async fn obfuscation_rotation_regenerates_configs() {
+    let client = server().await;
+    let user = client.create_user(&create("dave", "ops")).await.unwrap();
+    let before = client.obfuscation("default").await.unwrap();
+
+    let rotation = client.rotate_obfuscation("default").await.unwrap();
+    let p = rotation.params;
+    assert_ne!(p, before);
+    assert_eq!(client.obfuscation("default").await.unwrap(), p);
+    assert!(p.jmin < p.jmax && p.s1 + 56 != p.s2);
+    let h: Vec<u32> = [&p.h1, &p.h2, &p.h3, &p.h4].iter().map(|h| h.parse().unwrap()).collect();
+    assert!(h.iter().enumerate().all(|(i, v)| *v > 4 && !h[..i].contains(v)));
+
+    assert_eq!(rotation.regenerated.changed.len(), 1);
+    assert_eq!(rotation.regenerated.changed[0].id, user.id);
+    assert!(rotation.regenerated.changed[0].diff.contains(&format!("+H1 = {}", p.h1)));
+    assert!(client.regenerate_configs(true).await.unwrap().changed.is_empty());
+}
+
+#[tokio::test]
+async fn extended_params_follow_group_compat() {
+    let backend = Backend::fake();
+    let client = server_with(backend.clone()).await;
+    let new = client.create_user(&create("erin", "new")).await.unwrap();
+    client.create_user(&create("frank", "old")).await.unwrap();
+    let settings = GroupSettings { compat: AwgCompat::Legacy, ..Default::default() };
+    assert!(client.set_group_settings("old", &settings).await.unwrap().changed.is_empty());
+    assert_eq!(client.group_settings("old").await.unwrap(), settings);
+    assert_eq!(client.group_settings("new").await.unwrap().compat, AwgCompat::Full);
+
+    let wg_conf = backend.server().wg_conf();
+    let conf = backend.read_file(&wg_conf).await.unwrap()
+        .replace("H1 = 1403243210", "H1 = 1403243210-1403243299\nS3 = 20\nS4 = 8\nI1 = <b 0xf6ab3267fa><r 16>");
+    backend.write_file(&wg_conf, &conf).await.unwrap();
+
+    let report = client.regenerate_configs(true).await.unwrap();
+    assert_eq!(report.changed.len(), 1);
+    assert_eq!(report.changed[0].id, new.id);
+    let diff = &report.changed[0].diff;
+    assert!(diff.contains("+S3 = 20\n") && diff.contains("+S4 = 8\n") && diff.contains("+H1 = 1403243210-1403243299\n"));
+    assert!(diff.contains("+I1 = <b 0xf6ab3267fa><r 16>\n"));
+    assert!(!diff.contains("J1"));
+}
+
+#[tokio::test]
+async fn users_are_placed_on_servers() {
+    let fra = ServerConfig { host: "fra.example.com".to_string(), mask: "10.8.2.".to_string(), ..Default::default() };
+    let client = server_with_backends(vec![Backend::fake(), Backend::fake_named("fra", fra)]).await;
+
+    let servers = client.servers().await.unwrap();
+    assert_eq!(servers.iter().map(|s| s.name.as_str()).collect::<Vec<_>>(), ["default", "fra"]);
+
+    let req = CreateRequest { server: Some("fra".to_string()), ..create("gina", "eu") };
+    let gina = client.create_user(&req).await.unwrap();
+    assert_eq!(gina.server.as_deref(), Some("fra"));
+    assert_eq!(gina.placement.unwrap().reason, PlacementReason::Requested);
+
+    let hank = client.create_user(&create("hank", "eu")).await.unwrap();
+    assert_eq!(hank.server.as_deref(), Some("default"));
+    let placement = hank.placement.unwrap();
+    assert_eq!((placement.reason, placement.policy), (PlacementReason::Policy, Some(PlacementPolicy::LeastPeers)));
+    assert_eq!(placement.detail.as_deref(), Some("default: 0 peers, fra: 1 peers"));
+
+    let pinned = GroupSettings { server: Some("fra".to_string()), ..Default::default() };
+    client.set_group_settings("eu", &pinned).await.unwrap();
+    let ivan = client.create_user(&create("ivan", "eu")).await.unwrap();
+    assert_eq!(ivan.server.as_deref(), Some("fra"));
+    assert_eq!(ivan.placement.unwrap().reason, PlacementReason::Pinned);
+
+    let query = ListQuery { server: Some("fra".to_string()), ..Default::default() };
+    assert_eq!(client.list_users(&query).await.unwrap().total, 2);
+    assert_eq!(client.servers().await.unwrap()[1].users, 2);
+    assert!(client.obfuscation("fra").await.is_ok());
+
+    let req = CreateRequest { server: Some("nowhere".to_string()), ..create("jill", "eu") };
+    let err = client.create_user(&req).await.unwrap_err();
+    assert_eq!(err.status(), Some(StatusCode::UNPROCESSABLE_ENTITY));
+    let pinned = GroupSettings { server: Some("nowhere".to_string()), ..Default::default() };
+    assert_eq!(client.set_group_settings("eu", &pinned).await.unwrap_err().code(), Some("validation"));
+    assert_eq!(client.obfuscation("nowhere").await.unwrap_err().code(), Some("not_found"));
+
+    client.delete_user(&gina.id).await.unwrap();
+    assert_eq!(client.servers().await.unwrap()[1].users, 1);
+}
+
+#[tokio::test]
+async fn user_gets_configs_on_several_locations() {
+    let fra = ServerConfig { host: "fra.example.com".to_string(), mask: "10.8.2.".to_string(), ..Default::default() };
+    let client = server_with_backends(vec![Backend::fake(), Backend::fake_named("fra", fra)]).await;
+
+    let req = CreateRequest { server: Some("default".to_string()), locations: vec!["fra".to_string(), "default".to_string()], ..create("kate", "travel") };
+    let kate = client.create_user(&req).await.unwrap();
+    let servers: Vec<&str> = kate.locations.iter().map(|l| l.server.as_str()).collect();
+    assert_eq!(servers, ["default", "fra"]);
+    assert_eq!(kate.locations[0].id, kate.id);
+    assert!(kate.locations[1].address.as_deref().unwrap().starts_with("10.8.2."));
+    assert_eq!(client.list_users(&ListQuery::default()).await.unwrap().total, 1);
+    let query = ListQuery { server: Some("fra".to_string()), ..Default::default() };
+    assert_eq!(client.list_users(&query).await.unwrap().items[0].id, kate.id);
+    assert_eq!(client.user(&kate.locations[1].id).await.unwrap_err().code(), Some("not_found"));
+
+    let update = UpdateRequest { name: Some("katie".to_string()), group: Some("home".to_string()), ..Default::default() };
+    assert_eq!(client.update_user(&kate.id, &update).await.unwrap().locations.len(), 2);
+    assert_eq!(client.group_users("home", &ListQuery::default()).await.unwrap().total, 1);
+    assert_eq!(client.group_users("travel", &ListQuery::default()).await.unwrap().total, 0);
+
+    assert_eq!(client.add_location(&kate.id, "fra").await.unwrap_err().code(), Some("conflict"));
+    assert_eq!(client.remove_location(&kate.id, "default").await.unwrap_err().code(), Some("conflict"));
+    client.remove_location(&kate.id, "fra").await.unwrap();
+    assert_eq!(client.user(&kate.id).await.unwrap().locations.len(), 1);
+    assert_eq!(client.add_location(&kate.id, "fra").await.unwrap().locations.len(), 2);
+
+    client.delete_user(&kate.id).await.unwrap();
+    assert!(client.servers().await.unwrap().iter().all(|s| s.users == 0));
+}
+
+#[tokio::test]
+async fn user_owns_several_devices() {
+    let backend = Backend::fake();
+    let Backend::Fake(fake) = &backend else { unreachable!() };
+    let fake = fake.clone();
+    let client = server_with(backend).await;
+    let lisa = client.create_user(&create("lisa", "family")).await.unwrap();
+    assert_eq!(lisa.devices.len(), 1);
+    assert_eq!(lisa.devices[0].name, "main");
+
+    let laptop = client.add_device(&lisa.id, "laptop").await.unwrap();
+    assert_eq!(laptop.name, "laptop");
+    assert_ne!(laptop.address, lisa.devices[0].address);
+    assert_eq!(client.add_device(&lisa.id, "laptop").await.unwrap_err().code(), Some("conflict"));
+    assert_eq!(client.add_device(&laptop.id, "tablet").await.unwrap_err().code(), Some("not_found"));
+    assert_eq!(client.list_users(&ListQuery::default()).await.unwrap().total, 1);
+
+    let limit = UpdateRequest { device_limit: Some(2), ..Default::default() };
+    assert_eq!(client.update_user(&lisa.id, &limit).await.unwrap().device_limit, 2);
+    assert_eq!(client.add_device(&lisa.id, "tablet").await.unwrap_err().code(), Some("conflict"));
+
+    let usage = client.user_usage(&lisa.id).await.unwrap();
+    assert_eq!(usage.peers.len(), 2);
+    assert_eq!(usage.recv_bytes, 0);
+
+    // A rename that fails leaves the limit sent along with it unset.
+    let other = client.create_user(&create("lise", "family")).await.unwrap();
+    let clash = UpdateRequest { name: Some("lise".to_string()), device_limit: Some(5), ..Default::default() };
+    assert_eq!(client.update_user(&lisa.id, &clash).await.unwrap_err().code(), Some("conflict"));
+    assert_eq!(client.user(&lisa.id).await.unwrap().device_limit, 2);
+
+    let rename = UpdateRequest { name: Some("lise".to_string()), group: Some("home".to_string()), ..Default::default() };
+    client.update_user(&lisa.id, &rename).await.unwrap();
+    assert!(client.user_usage(&lisa.id).await.unwrap().peers.iter().all(|p| p.name == "lise"));
+
+    assert_eq!(client.remove_device(&lisa.id, &lisa.id).await.unwrap_err().code(), Some("conflict"));
+    client.remove_device(&lisa.id, &laptop.id).await.unwrap();
+    assert_eq!(client.devices(&lisa.id).await.unwrap().len(), 1);
+    client.add_device(&lisa.id, "phone").await.unwrap();
+    // The user and its devices go in one write, or not at all.
+    fake.fail_next("check_conf");
+    assert!(client.delete_user(&lisa.id).await.is_err());
+    assert_eq!(client.devices(&lisa.id).await.unwrap().len(), 2);
+    let writes = fake.calls("check_conf");
+    client.delete_user(&lisa.id).await.unwrap();
+    assert_eq!(fake.calls("check_conf"), writes + 1);
+    client.delete_user(&other.id).await.unwrap();
+    assert!(client.stats(&StatsQuery::default()).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn user_brings_own_key() {
+    let client = server().await;
+    let key = "mFsNjM0x4tGA7ZsBmNwFPQ0JW5F5Bt8tJtjkP3UVQEI=";
+    let req = CreateRequest { public_key: Some(key.to_string()), ..create("mona", "byok") };
+    let mona = client.create_user(&req).await.unwrap();
+    assert_eq!(mona.id, key);
+    assert!(mona.own_key);
+    let keepalive = format!("PersistentKeepalive = {}", config().keepalive);
+    let config = mona.config.unwrap();
+    assert!(config.contains("PrivateKey = <your private key>"));
+    assert!(config.contains("PresharedKey = "));
+    assert!(config.contains(&keepalive));
+    assert!(client.user(key).await.unwrap().config.is_none());
+
+    assert_eq!(client.create_user(&req).await.unwrap_err().code(), Some("validation"));
+    let req = CreateRequest { public_key: Some("not a key".to_string()), ..create("nina", "byok") };
+    assert_eq!(client.create_user(&req).await.unwrap_err().code(), Some("validation"));
+}
+
+#[tokio::test]
+async fn user_keys_are_rotated() {
+    let client = server().await;
+    let olga = client.create_user(&create("olga", "rotate")).await.unwrap();
+    let laptop = client.add_device(&olga.id, "laptop").await.unwrap();
+
+    let rotated = client.rotate_keys(&olga.id).await.unwrap();
+    assert_ne!(rotated.id, olga.id);
+    assert_eq!((rotated.name.as_str(), rotated.group.as_deref()), ("olga", Some("rotate")));
+    assert_eq!(rotated.locations[0].address, olga.locations[0].address);
+    assert!(rotated.config.is_none());
+    assert!(rotated.devices.iter().any(|d| d.id == laptop.id));
+    assert_eq!(client.user(&olga.id).await.unwrap_err().code(), Some("not_found"));
+    assert_eq!(client.user_usage(&rotated.id).await.unwrap().peers.len(), 2);
+    assert_eq!(client.rotate_keys(&olga.id).await.unwrap_err().code(), Some("not_found"));
+    client.delete_user(&rotated.id).await.unwrap();
+    assert!(client.stats(&StatsQuery::default()).await.unwrap().is_empty());
+
+    let key = "mFsNjM0x4tGA7ZsBmNwFPQ0JW5F5Bt8tJtjkP3UVQEI=";
+    let req = CreateRequest { public_key: Some(key.to_string()), ..create("pia", "rotate") };
+    let pia = client.create_user(&req).await.unwrap();
+    let rotated = client.rotate_keys(key).await.unwrap();
+    assert_eq!(rotated.id, key);
+    assert_ne!(rotated.config, pia.config);
+}
+
+#[tokio::test]
+async fn server_key_is_rotated() {
+    let backend = Backend::fake();
+    let Backend::Fake(fake) = &backend else { unreachable!() };
+    let fake = fake.clone();
+    let client = server_with(backend).await;
+    let rita = client.create_user(&create("rita", "keys")).await.unwrap();
+    let sam = client.create_user(&create("sam", "keys")).await.unwrap();
+
+    let before = client.server_key("default").await.unwrap();
+    assert!(before.rotated_at.is_none());
+    assert!(before.pending.is_empty());
+
+    let rotation = client.rotate_server_key("default").await.unwrap();
+    assert_ne!(rotation.public_key, before.public_key);
+    assert_eq!(rotation.regenerated.changed.len(), 2);
+    assert!(rotation.regenerated.changed.iter().all(|c| c.diff.contains(&rotation.public_key)));
+
+    fake.handshake(&sam.id);
+    let status = client.server_key("default").await.unwrap();
+    assert_eq!(status.public_key, rotation.public_key);
+    assert_eq!(status.total, 2);
+    let pending: Vec<&str> = status.pending.iter().map(|p| p.id.as_str()).collect();
+    assert_eq!(pending, [rita.id.as_str()]);
+    assert_eq!(client.rotate_server_key("nowhere").await.unwrap_err().code(), Some("not_found"));
+}
+
+#[tokio::test]
+async fn fixed_addresses_are_reserved() {
+    let fra = ServerConfig { host: "fra.example.com".to_string(), mask: "10.8.2.".to_string(), reserved: vec!["10.8.2.2-10.8.2.3".to_string()], ..Default::default() };
+    let client = server_with_backends(vec![Backend::fake(), Backend::fake_named("fra", fra)]).await;
+
+    let router = CreateRequest { address: Some("10.8.2.3".to_string()), ..create("router", "office") };
+    let router = client.create_user(&router).await.unwrap();
+    assert_eq!(router.server.as_deref(), Some("fra"));
+    assert_eq!(router.locations[0].address.as_deref(), Some("10.8.2.3"));
+
+    let req = CreateRequest { server: Some("fra".to_string()), ..create("tom", "office") };
+    let tom = client.create_user(&req).await.unwrap();
+    assert_eq!(tom.locations[0].address.as_deref(), Some("10.8.2.4"));
+
+    let taken = CreateRequest { address: Some("10.8.2.3".to_string()), ..create("box", "office") };
+    assert_eq!(client.create_user(&taken).await.unwrap_err().code(), Some("validation"));
+    let outside = CreateRequest { server: Some("default".to_string()), address: Some("10.8.2.9".to_string()), ..create("box", "office") };
+    assert_eq!(client.create_user(&outside).await.unwrap_err().code(), Some("validation"));
+    let server_ip = CreateRequest { address: Some("10.8.2.1".to_string()), ..create("box", "office") };
+    assert_eq!(client.create_user(&server_ip).await.unwrap_err().code(), Some("validation"));
+}
+
+#[tokio::test]
+async fn sites_route_subnets() {
+    let backend = Backend::fake();
+    let Backend::Fake(fake) = &backend else { unreachable!() };
+    let fake = fake.clone();
+    let client = server_with(backend).await;
+
+    let site = Site { routes: vec!["192.168.10.0/24".to_string()], allowed_ips: vec!["10.8.1.0/24".to_string()] };
+    // With its own key, so the response carries the config.
+    let public_key = Some("mFsNjM0x4tGA7ZsBmNwFPQ0JW5F5Bt8tJtjkP3UVQEI=".to_string());
+    let req = CreateRequest { site: Some(site.clone()), public_key, ..create("branch", "sites") };
+    let branch = client.create_user(&req).await.unwrap();
+    assert_eq!(branch.site, Some(site));
+    let config = branch.config.unwrap();
+    assert!(!config.contains("DNS"));
+    assert!(config.contains("AllowedIPs = 10.8.1.0/24"));
+    let wg_conf = fake.read_file(&fake.server.wg_conf()).unwrap();
+    assert!(wg_conf.contains(&format!("AllowedIPs = {}/32, 192.168.10.0/24", branch.locations[0].address.as_deref().unwrap())));
+
+    let overlaps = |routes: &[&str]| CreateRequest {
+        site: Some(Site { routes: routes.iter().map(|r| r.to_string()).collect(), ..Default::default() }),
+        ..create("other", "sites")
+    };
+    for routes in [&["192.168.10.128/25"][..], &["10.8.0.0/16"], &["192.168.20.1/24"], &[]] {
+        assert_eq!(client.create_user(&overlaps(routes)).await.unwrap_err().code(), Some("validation"), "{:?}", routes);
+    }
+    client.create_user(&overlaps(&["192.168.20.0/24"])).await.unwrap();
+}
+
+#[tokio::test]
+async fn groups_are_isolated() {
+    let backend = Backend::fake();
+    let Backend::Fake(fake) = &backend else { unreachable!() };
+    let fake = fake.clone();
+    let client = server_with(backend).await;
+
+    let guest = client.create_user(&create("guest", "guests")).await.unwrap();
+    let address = guest.locations[0].address.clone().unwrap();
+    assert!(client.rules("default").await.unwrap().rules.is_empty());
+
+    let mut settings = client.group_settings("guests").await.unwrap();
+    settings.isolation = Some(Isolation { allow: vec!["not a subnet".to_string()], ..Default::default() });
+    assert_eq!(client.set_group_settings("guests", &settings).await.unwrap_err().code(), Some("validation"));
+
+    settings.isolation = Some(Isolation { allow: vec!["10.8.1.1".to_string()], ..Default::default() });
+    client.set_group_settings("guests", &settings).await.unwrap();
+    let ruleset = client.rules("default").await.unwrap();
+    assert!(ruleset.rules.contains(&format!("-A AWG-ISOLATION -s {}/32 -d 10.8.1.1/32 -j ACCEPT", address)));
+    assert!(ruleset.rules.contains(&format!("-A AWG-ISOLATION -s {}/32 -d 10.8.1.0/24 -j DROP", address)));
+    assert_eq!(fake.read_file(&fake.server.isolation_script()).unwrap(), ruleset.script);
+    assert_eq!(fake.live_rules(), Some(ruleset.rules.clone()));
+
+    // A site's networks are held to its group's rules like its own address.
+    let site = Site { routes: vec!["192.168.50.0/24".to_string()], allowed_ips: vec![] };
+    let office = client.create_user(&CreateRequest { site: Some(site), ..create("office", "guests") }).await.unwrap();
+    let office_address = office.locations[0].address.clone().unwrap();
+    let rules = client.rules("default").await.unwrap().rules;
+    for source in [format!("{}/32", office_address), "192.168.50.0/24".to_string()] {
+        assert!(rules.contains(&format!("-A AWG-ISOLATION -s {} -d 10.8.1.1/32 -j ACCEPT", source)));
+        assert!(rules.contains(&format!("-A AWG-ISOLATION -s {} -d 10.8.1.0/24 -j DROP", source)));
+    }
+    assert_eq!(fake.live_rules(), Some(rules));
+    client.delete_user(&office.id).await.unwrap();
+
+    // Other groups keep talking to everyone.
+    client.create_user(&create("staff", "staff")).await.unwrap();
+    assert_eq!(client.rules("default").await.unwrap().rules.len(), ruleset.rules.len());
+
+    client.delete_user(&guest.id).await.unwrap();
+    assert!(client.rules("default").await.unwrap().rules.is_empty());
+}
+
+#[tokio::test]
+async fn lost_isolation_rules_are_reloaded() {
+    let backend = Backend::fake();
+    let Backend::Fake(fake) = &backend else { unreachable!() };
+    let fake = fake.clone();
+    let client = server_with(backend).await;
+
+    client.create_user(&create("guest", "guests")).await.unwrap();
+    let isolated = GroupSettings { isolation: Some(Isolation::default()), ..Default::default() };
+    fake.fail_next("load_rules");
+    client.set_group_settings("guests", &isolated).await.unwrap();
+    assert_eq!(fake.live_rules(), None);
+    assert!(fake.read_file(&fake.server.isolation_script()).is_err());
+
+    // The next write retries the rules that failed to load.
+    client.create_user(&create("staff", "staff")).await.unwrap();
+    let ruleset = client.rules("default").await.unwrap();
+    assert_eq!(fake.live_rules(), Some(ruleset.rules.clone()));
+    assert_eq!(fake.read_file(&fake.server.isolation_script()).unwrap(), ruleset.script);
+    let loads = fake.calls("load_rules");
+    client.create_user(&create("staff2", "staff")).await.unwrap();
+    assert_eq!(fake.calls("load_rules"), loads);
+
+    // A restarted container comes back without them.
+    fake.flush_rules();
+    client.create_user(&create("staff3", "staff")).await.unwrap();
+    assert_eq!(fake.live_rules(), Some(ruleset.rules.clone()));
+
+    // A rule edited by hand is put back, though the count still matches.
+    fake.load_rules(&ruleset.script.replace(" -j DROP", " -j ACCEPT")).unwrap();
+    client.create_user(&create("staff4", "staff")).await.unwrap();
+    assert_eq!(fake.live_rules(), Some(ruleset.rules));
+}
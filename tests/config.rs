@@ -0,0 +1,54 @@
+use std::{path::Path, process::{Command, Output}};
+
+use simple_awg_api::config::Config;
+
+/// Runs the server binary with `--print-config` in an empty directory, so no
+/// `.env` is picked up, with only `env` set.
+fn print_config(toml: &str, env: &[(&str, &str)]) -> Output {
+    let dir = std::env::temp_dir().join(format!("config-{}", uuid::Uuid::new_v4().simple()));
+    std::fs::create_dir(&dir).unwrap();
+    std::fs::write(dir.join("config.toml"), toml).unwrap();
+    let templates = Path::new(env!("CARGO_MANIFEST_DIR")).join("data/templates");
+    let output = Command::new(env!("CARGO_BIN_EXE_simple-awg-api"))
+        .args(["--config", "config.toml", "--print-config"])
+        .current_dir(&dir)
+        .env_clear()
+        .env("TEMPLATES_DIR", templates)
+        .envs(env.iter().copied())
+        .output()
+        .unwrap();
+    std::fs::remove_dir_all(&dir).ok();
+    output
+}
+
+const TOML: &str = r#"
+host = "toml.example.com"
+mask = "10.8.2."
+dns = "9.9.9.9"
+keepalive = "15"
+reserved = ["10.8.2.10"]
+"#;
+
+#[test]
+fn env_overrides_the_file() {
+    let output = print_config(TOML, &[("HOST", "env.example.com"), ("NAME_MAX_LEN", "20")]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let config: Config = toml::from_str(&String::from_utf8(output.stdout).unwrap()).unwrap();
+    assert_eq!(config.host, "env.example.com");
+    assert_eq!(config.name_max_len, 20);
+    // The file's keys the environment leaves alone, and defaults for the rest.
+    assert_eq!((config.mask.as_str(), config.dns.as_str(), config.keepalive.as_str()), ("10.8.2.", "9.9.9.9", "15"));
+    assert_eq!(config.reserved, ["10.8.2.10"]);
+    assert_eq!(config.addr, Config::default().addr);
+}
+
+#[test]
+fn invalid_config_is_reported() {
+    let output = print_config(TOML, &[("KEEPALIVE", "soon")]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("keepalive: \"soon\" is not a number of seconds"));
+
+    let output = print_config(TOML, &[("NAME_MAX_LEN", "many")]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Invalid env var NAME_MAX_LEN"));
+}
@@ -0,0 +1,80 @@
+use std::{sync::Arc, time::Duration};
+
+use simple_awg_api::{config::{self, config, Config, ServerConfig}, interactions::{backend::Backend, fake::FakeBackend, schedule::{rotate_on_schedule, sample_on_schedule}, shared::AppState}};
+use simple_awg_api_client::types::{CreateRequest, GroupSettings, PlacementPolicy};
+
+fn state_with(backends: Vec<Backend>) -> AppState {
+    let stored = std::env::temp_dir().join(format!("{}.save", uuid::Uuid::new_v4().simple()));
+    AppState::with_backends(backends, &stored.to_string_lossy())
+}
+
+fn fake(backend: &Backend) -> Arc<FakeBackend> {
+    let Backend::Fake(fake) = backend else { unreachable!() };
+    fake.clone()
+}
+
+fn create(name: &str, group: &str, server: &str) -> CreateRequest {
+    CreateRequest { name: name.to_string(), group: group.to_string(), server: Some(server.to_string()), ..Default::default() }
+}
+
+/// Sets the bytes every peer of `fake` has received, as `clientsTable` reports them.
+fn set_received(fake: &FakeBackend, received: &str) {
+    let path = fake.server.clients_table();
+    let mut table: serde_json::Value = serde_json::from_str(&fake.read_file(&path).unwrap()).unwrap();
+    for record in table.as_array_mut().unwrap() {
+        record["userData"]["dataReceived"] = received.into();
+    }
+    fake.write_file(&path, &table.to_string());
+}
+
+#[tokio::test(start_paused = true)]
+async fn traffic_is_sampled_without_writes() {
+    let fra = ServerConfig { host: "fra.example.com".to_string(), mask: "10.8.2.".to_string(), ..Default::default() };
+    let (ams, fra) = (Backend::fake(), Backend::fake_named("fra", fra));
+    let state = state_with(vec![ams.clone(), fra.clone()]);
+    state.add_user(create("anna", "eu", "default")).await.unwrap();
+    state.add_user(create("fred", "eu", "fra")).await.unwrap();
+    let policy = GroupSettings { placement: Some(PlacementPolicy::LeastTraffic), ..Default::default() };
+    state.set_group_settings("eu", policy).await.unwrap();
+    sample_on_schedule(state.clone());
+
+    // A burst on one server right after the last write, then nothing for
+    // well over an hour while neither server is written to.
+    set_received(&fake(&ams), "10 GiB");
+    tokio::time::sleep(Duration::from_secs(90 * 60)).await;
+    set_received(&fake(&fra), "1 MiB");
+
+    let (id, _) = state.add_user(CreateRequest { server: None, ..create("gina", "eu", "") }).await.unwrap();
+    let gina = state.user(&id).await.unwrap();
+    assert_eq!(gina.server.as_deref(), Some("default"));
+    assert_eq!(gina.placement.unwrap().detail.as_deref(), Some("default: 0 recent bytes, fra: 1048576 recent bytes"));
+}
+
+#[tokio::test(start_paused = true)]
+async fn obfuscation_is_rotated_on_schedule() {
+    let backend = Backend::fake();
+    let fake = fake(&backend);
+    let state = state_with(vec![backend]);
+    let (id, _) = state.add_user(create("anna", "eu", "default")).await.unwrap();
+    let before = state.obfuscation("default").await.unwrap();
+    config::replace(Config { obfuscation_rotate_hours: 2, ..(*config()).clone() });
+    rotate_on_schedule(state.clone());
+
+    tokio::time::sleep(Duration::from_secs(119 * 60)).await;
+    assert_eq!(fake.calls("restart_interface"), 0);
+    tokio::time::sleep(Duration::from_secs(2 * 60)).await;
+    assert_eq!(fake.calls("restart_interface"), 1);
+    let rotated = state.obfuscation("default").await.unwrap();
+    assert_ne!(rotated, before);
+    assert!(state.client_config(&id).await.unwrap().contains(&format!("Jc = {}\n", rotated.jc)));
+
+    // A failed restart puts the interface back on the current parameters,
+    // and the next period tries again.
+    fake.fail_next("restart_interface");
+    tokio::time::sleep(Duration::from_secs(2 * 3600)).await;
+    assert_eq!(fake.calls("restart_interface"), 3);
+    assert_eq!(state.obfuscation("default").await.unwrap(), rotated);
+    tokio::time::sleep(Duration::from_secs(2 * 3600)).await;
+    assert_eq!(fake.calls("restart_interface"), 4);
+    assert_ne!(state.obfuscation("default").await.unwrap(), rotated);
+}
@@ -0,0 +1,541 @@
+//! Request and response bodies of the simple-awg-api HTTP API, shared by the
+//! server and the client. The `openapi` feature derives the utoipa schemas.
+
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "openapi")]
+use utoipa::{IntoParams, ToSchema};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub struct CreateRequest {
+    pub name: String,
+    pub group: String,
+    /// Server to put the peer on. Defaults to the group's server.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server: Option<String>,
+    /// Further servers to give the user a config for, each with its own keys
+    /// and address. Servers already holding one of its configs are skipped.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub locations: Vec<String>,
+    /// WireGuard public key the user generated itself. No private key is
+    /// generated or stored then, and the config carries a placeholder for it.
+    /// Can't be combined with `locations`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub public_key: Option<String>,
+    /// Fixed address for the peer, e.g. `10.8.1.50`, instead of the next free
+    /// one. May be one of the server's reserved addresses. Without `server`,
+    /// the user goes to the server whose subnet holds it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+    /// Makes the peer a site: a router with networks behind it. Can't be
+    /// combined with `locations`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub site: Option<Site>,
+}
+
+/// A router connecting the networks behind it, e.g. a branch office LAN.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub struct Site {
+    /// IPv4 networks behind the router that the server routes to it, such as
+    /// `192.168.10.0/24`. They must not overlap the VPN subnet or other peers.
+    pub routes: Vec<String>,
+    /// What the router sends through the tunnel, written to its config's
+    /// `AllowedIPs`. Defaults to everything.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_ips: Vec<String>,
+}
+
+/// Body of `POST /v1/users/{id}/locations`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub struct LocationRequest {
+    pub server: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub struct UpdateRequest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Moves the user's config to this group's page.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    /// Devices the user may have; 0 lifts the limit.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device_limit: Option<usize>,
+}
+
+/// Body of `POST /v1/users/{id}/devices`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub struct DeviceRequest {
+    /// Unique among the user's devices, e.g. `laptop`.
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[cfg_attr(feature = "openapi", derive(IntoParams))]
+#[cfg_attr(feature = "openapi", into_params(parameter_in = Query))]
+pub struct ListQuery {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    /// Substring of the user name.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub server: Option<String>,
+    #[serde(default)]
+    pub offset: usize,
+    /// Defaults to 100, capped at 1000.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[cfg_attr(feature = "openapi", derive(IntoParams))]
+#[cfg_attr(feature = "openapi", into_params(parameter_in = Query))]
+pub struct StatsQuery {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub total: usize,
+    pub offset: usize,
+    pub limit: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub struct GroupRecord {
+    pub group: String,
+    pub guid: String,
+    /// Only when creating a user with its own key: the config template to
+    /// fill the private key into.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub config: Option<String>,
+}
+
+/// Which AmneziaWG clients a group's configs are written for.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum AwgCompat {
+    /// Every parameter the server sets, for AmneziaWG 1.5/2.0 clients.
+    #[default]
+    Full,
+    /// Only Jc, Jmin, Jmax, S1, S2 and single H1-H4 values, for AmneziaWG 1.0 clients.
+    Legacy,
+}
+
+/// How a server is picked for a new user that names none and whose group is
+/// not pinned to one.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum PlacementPolicy {
+    /// The server with the fewest peers.
+    #[default]
+    LeastPeers,
+    /// The server whose peers moved the fewest bytes in the last hour, from the
+    /// `clientsTable` counters sampled as they are read.
+    LeastTraffic,
+    /// The server with the most unallocated addresses in its subnet.
+    FreeAddresses,
+}
+
+impl std::str::FromStr for PlacementPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "least_peers" => Ok(Self::LeastPeers),
+            "least_traffic" => Ok(Self::LeastTraffic),
+            "free_addresses" => Ok(Self::FreeAddresses),
+            _ => Err(format!("unknown placement policy {:?}", s)),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub struct GroupSettings {
+    #[serde(default)]
+    pub compat: AwgCompat,
+    /// Server the group is pinned to: new users of the group go there when
+    /// they don't name one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server: Option<String>,
+    /// Placement policy for the group's new users when it is not pinned.
+    /// Defaults to the `placement` setting.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub placement: Option<PlacementPolicy>,
+    /// Limits what the group's peers can reach. Without it, everything,
+    /// other peers included.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub isolation: Option<Isolation>,
+}
+
+/// What a group's peers may reach through their server, enforced with
+/// firewall rules on it. Replies to connections other peers open to them are
+/// always let through.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub struct Isolation {
+    /// Anything outside the VPN subnet and the networks behind sites.
+    #[serde(default = "yes")]
+    pub internet: bool,
+    /// IPv4 subnets reachable regardless, e.g. `10.8.1.0/24` for the whole VPN subnet.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allow: Vec<String>,
+}
+
+impl Default for Isolation {
+    fn default() -> Self {
+        Self { internet: true, allow: vec![] }
+    }
+}
+
+fn yes() -> bool {
+    true
+}
+
+/// The firewall rules enforcing the groups' isolation on a server.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub struct Ruleset {
+    pub server: String,
+    /// `iptables` rules of the isolation chain, in order.
+    pub rules: Vec<String>,
+    /// Shell script run in the container to load them.
+    pub script: String,
+}
+
+/// Why a user was put on its server.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum PlacementReason {
+    /// Named in the create request.
+    Requested,
+    /// The group is pinned to it.
+    Pinned,
+    /// Picked by a placement policy.
+    Policy,
+}
+
+/// The placement decision made when a user was created.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub struct Placement {
+    pub reason: PlacementReason,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub policy: Option<PlacementPolicy>,
+    /// What the policy compared, e.g. `default: 4 peers, fra: 2 peers`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+/// An AmneziaWG server the API manages.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub struct ServerInfo {
+    pub name: String,
+    /// Shown on share pages to tell a user's configs apart.
+    pub location: String,
+    /// Endpoint host in client configs.
+    pub host: String,
+    pub interface: String,
+    /// Peers stored for this server.
+    pub users: usize,
+}
+
+/// A user as exposed by the `/v1` API.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub struct UserResource {
+    pub id: String,
+    pub name: String,
+    pub group: Option<String>,
+    pub guid: Option<String>,
+    /// Server the peer lives on.
+    pub server: Option<String>,
+    /// How the server was chosen; absent for users created before placement was recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub placement: Option<Placement>,
+    /// Every config of the user, its own server's first.
+    #[serde(default)]
+    pub locations: Vec<UserLocation>,
+    /// The user's devices, its first one (the user's own peer) first.
+    #[serde(default)]
+    pub devices: Vec<Device>,
+    /// Devices the user may have; 0 means no limit.
+    #[serde(default)]
+    pub device_limit: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub site: Option<Site>,
+    /// The user brought its own key; its configs have a `PrivateKey` placeholder.
+    #[serde(default)]
+    pub own_key: bool,
+    /// The user's peers are commented out of `wg0.conf` and can't connect.
+    #[serde(default)]
+    pub suspended: bool,
+    /// Only in the response creating a user with its own key: the config
+    /// template to fill the private key into.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub config: Option<String>,
+    pub created: String,
+}
+
+/// A device peer of a user, with its own keys and address on the user's server.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub struct Device {
+    /// Peer id (public key).
+    pub id: String,
+    /// `main` for the user's own peer.
+    pub name: String,
+    pub server: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+    pub created: String,
+}
+
+/// Traffic of every peer of a user, devices and locations alike.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub struct UserUsage {
+    pub id: String,
+    pub name: String,
+    pub recv_bytes: u64,
+    pub sent_bytes: u64,
+    pub peers: Vec<UserStats>,
+}
+
+/// One of a user's configs.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub struct UserLocation {
+    pub server: String,
+    pub location: String,
+    /// Peer id (public key) of the config on that server.
+    pub id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub struct User {
+    pub uid: String,
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub struct UserStats {
+    pub uid: String,
+    pub name: String,
+    pub recv: String,
+    pub sent: String,
+    pub last_seen: String,
+    pub created: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Running,
+    Completed,
+    CompletedWithErrors,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum ItemStatus {
+    Pending,
+    Ok,
+    Failed,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub struct JobItem {
+    pub name: String,
+    pub group: String,
+    pub status: ItemStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub record: Option<GroupRecord>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub struct Job {
+    pub id: String,
+    pub status: JobStatus,
+    pub total: usize,
+    pub done: usize,
+    pub failed: usize,
+    pub created: String,
+    pub finished: Option<String>,
+    pub items: Vec<JobItem>,
+    pub records: Vec<GroupRecord>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub struct JobRef {
+    pub id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: impl Into<String>, message: impl Into<String>) -> Self {
+        Self { field: field.into(), message: message.into() }
+    }
+}
+
+/// Body of every error response: a stable machine-readable `code`, a human
+/// message and, for `validation`, the offending fields.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub struct ErrorBody {
+    pub code: String,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fields: Vec<FieldError>,
+}
+
+/// Outcome of a config and template reload.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub struct ReloadReport {
+    /// Keys that changed in the file but only take effect after a restart.
+    pub restart_required: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[cfg_attr(feature = "openapi", derive(IntoParams))]
+#[cfg_attr(feature = "openapi", into_params(parameter_in = Query))]
+pub struct RegenerateQuery {
+    /// Only report what would change.
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// A client config that differs from what the current settings render.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub struct ConfigChange {
+    pub id: String,
+    pub name: String,
+    pub group: String,
+    /// Removed (`-`) and added (`+`) lines; unchanged lines are left out.
+    pub diff: String,
+}
+
+/// A stored config that could not be rebuilt.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub struct SkippedConfig {
+    pub id: String,
+    pub name: String,
+    pub group: String,
+    pub reason: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub struct RegenerateReport {
+    pub dry_run: bool,
+    /// Stored configs looked at.
+    pub total: usize,
+    pub changed: Vec<ConfigChange>,
+    pub skipped: Vec<SkippedConfig>,
+}
+
+/// AmneziaWG obfuscation parameters of the server interface. Every client
+/// config carries the same values.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub struct ObfuscationParams {
+    /// Junk packets sent before the handshake.
+    pub jc: u32,
+    pub jmin: u32,
+    pub jmax: u32,
+    /// Junk bytes prepended to the handshake initiation.
+    pub s1: u32,
+    /// Junk bytes prepended to the handshake response.
+    pub s2: u32,
+    /// Message type headers replacing WireGuard's 1-4: a value, or a range
+    /// such as `100-200` on AmneziaWG 2.0.
+    pub h1: String,
+    pub h2: String,
+    pub h3: String,
+    pub h4: String,
+}
+
+/// Outcome of a server keypair rotation.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub struct ServerKeyRotation {
+    pub server: String,
+    /// The server's new public key.
+    pub public_key: String,
+    /// RFC 3339.
+    pub rotated_at: String,
+    /// Client configs rebuilt with the new key.
+    pub regenerated: RegenerateReport,
+}
+
+/// Clients of a server that have not connected since its keypair was last
+/// rotated. A client can only complete a handshake once it imported its new
+/// config, so these are the ones still to fetch it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub struct ServerKeyStatus {
+    pub server: String,
+    pub public_key: String,
+    /// RFC 3339; absent if the key was never rotated through the API.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rotated_at: Option<String>,
+    /// Peers on the server.
+    pub total: usize,
+    pub pending: Vec<PendingClient>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub struct PendingClient {
+    pub id: String,
+    pub name: String,
+    pub group: Option<String>,
+    /// RFC 3339 time of the last handshake, from before the rotation.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latest_handshake: Option<String>,
+}
+
+/// Outcome of an obfuscation parameter rotation.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub struct ObfuscationRotation {
+    pub params: ObfuscationParams,
+    /// Client configs rebuilt with the new parameters.
+    pub regenerated: RegenerateReport,
+}